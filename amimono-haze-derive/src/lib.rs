@@ -0,0 +1,167 @@
+//! Derive macros for the CRDT traits in `amimono_haze::crdt`, which re-exports
+//! them. Use them from there rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Ident, TokenStream as TokenStream2, TokenTree};
+use quote::{ToTokens, format_ident, quote};
+use syn::{
+    Data, DeriveInput, Field, Fields, Index, LitStr, Member, Path, Type, parse_macro_input,
+    parse_quote,
+};
+
+/// Derive `Crdt` for a struct by merging each field into the same field.
+///
+/// The types of merged fields that use the struct's type parameters must
+/// implement `Crdt`. The merge of a field can be changed with attributes:
+///
+/// - `#[crdt(skip)]` keeps this value's field and ignores the other one. This
+///   is only correct for fields that are the same on every copy of the value,
+///   or that don't take part in the value's state, such as caches.
+///
+/// - `#[crdt(merge_with = "path")]` merges the field with a function of type
+///   `fn(&mut T, T)`, for fields whose type doesn't implement `Crdt` or that
+///   should be merged differently. The function must be commutative,
+///   associative, and idempotent, like `Crdt::merge_from`.
+///
+/// Enums and unions are rejected. See the re-export in `amimono_haze::crdt` for
+/// examples.
+#[proc_macro_derive(Crdt, attributes(crdt))]
+pub fn derive_crdt(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_crdt(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derive `StoredCrdt` for a type that already implements `Crdt`,
+/// `Serialize`, and `Deserialize`.
+#[proc_macro_derive(StoredCrdt)]
+pub fn derive_stored_crdt(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_stored_crdt(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// How a field is merged.
+enum Merge {
+    Crdt,
+    Skip,
+    With(Path),
+}
+
+fn field_merge(field: &Field) -> syn::Result<Merge> {
+    let mut merge = Merge::Crdt;
+    for attr in field.attrs.iter().filter(|x| x.path().is_ident("crdt")) {
+        attr.parse_nested_meta(|meta| {
+            if !matches!(merge, Merge::Crdt) {
+                return Err(meta.error("only one of `skip` and `merge_with` may be given"));
+            }
+            if meta.path.is_ident("skip") {
+                merge = Merge::Skip;
+                Ok(())
+            } else if meta.path.is_ident("merge_with") {
+                let path: LitStr = meta.value()?.parse()?;
+                merge = Merge::With(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `skip` or `merge_with`"))
+            }
+        })?;
+    }
+    Ok(merge)
+}
+
+fn struct_fields<'a>(input: &'a DeriveInput, derive: &str) -> syn::Result<Vec<&'a Field>> {
+    match &input.data {
+        Data::Struct(data) => Ok(match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect(),
+            Fields::Unnamed(fields) => fields.unnamed.iter().collect(),
+            Fields::Unit => Vec::new(),
+        }),
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            format!("`{derive}` can only be derived for structs"),
+        )),
+    }
+}
+
+/// Whether a type mentions any of the type parameters.
+fn mentions(tokens: TokenStream2, params: &[Ident]) -> bool {
+    tokens.into_iter().any(|x| match x {
+        TokenTree::Ident(ident) => params.contains(&ident),
+        TokenTree::Group(group) => mentions(group.stream(), params),
+        _ => false,
+    })
+}
+
+fn expand_crdt(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut bindings = Vec::new();
+    let mut merges = Vec::new();
+    let mut bounded = Vec::new();
+    for (i, field) in struct_fields(&input, "Crdt")?.into_iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        };
+        let other = format_ident!("__other_{}", i);
+        match field_merge(field)? {
+            Merge::Crdt => {
+                bindings.push(quote!(#member: #other));
+                bounded.push(field.ty.clone());
+                merges.push(quote! {
+                    ::amimono_haze::crdt::Crdt::merge_from(&mut self.#member, #other);
+                });
+            }
+            Merge::Skip => bindings.push(quote!(#member: _)),
+            Merge::With(path) => {
+                bindings.push(quote!(#member: #other));
+                merges.push(quote!(#path(&mut self.#member, #other);));
+            }
+        }
+    }
+
+    let params: Vec<Ident> = (input.generics.type_params())
+        .map(|x| x.ident.clone())
+        .collect();
+    let bounds: Vec<Type> = bounded
+        .into_iter()
+        .filter(|ty| mentions(ty.to_token_stream(), &params))
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for ty in bounds {
+        (where_clause.predicates).push(parse_quote!(#ty: ::amimono_haze::crdt::Crdt));
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::amimono_haze::crdt::Crdt for #name #ty_generics #where_clause {
+            fn merge_from(&mut self, other: Self) {
+                let Self { #(#bindings),* } = other;
+                #(#merges)*
+            }
+        }
+    })
+}
+
+fn expand_stored_crdt(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if !input.generics.params.is_empty() {
+        let (_, ty_generics, _) = input.generics.split_for_impl();
+        let ty: syn::Type = parse_quote!(#name #ty_generics);
+        input
+            .generics
+            .make_where_clause()
+            .predicates
+            .push(parse_quote! {
+                #ty: ::amimono_haze::crdt::Crdt
+                    + ::amimono_haze::__private::serde::Serialize
+                    + ::amimono_haze::__private::serde::de::DeserializeOwned
+                    + 'static
+            });
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::amimono_haze::crdt::StoredCrdt for #name #ty_generics #where_clause {}
+    })
+}
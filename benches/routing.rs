@@ -0,0 +1,102 @@
+//! Measures the cost of routing a single request: hashing the key and finding
+//! the node that owns it, with and without a ring update in progress.
+//!
+//! Run with `cargo bench --bench routing`.
+
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use amimono_haze::crdt::{
+    CompositeKey,
+    partition::{PartitionerKind, Placement},
+    ring::{NetworkId, RingConfig, RingKey, RingUpdateConfig, VirtualNodeId},
+};
+
+const VNODES_PER_NODE: usize = 16;
+const KEYS: usize = 10_000;
+const ROUNDS: usize = 20;
+
+fn ring_config(nodes: usize, partitioner: PartitionerKind, updating: bool) -> RingConfig {
+    let mut vnodes = HashMap::new();
+    for n in 0..nodes {
+        let ni = NetworkId(format!("node-{n}"));
+        for i in 0..VNODES_PER_NODE {
+            vnodes.insert(VirtualNodeId(format!("{}/{i:02x}", ni.0)), ni.clone());
+        }
+    }
+    let update = updating.then(|| RingUpdateConfig::ToAdd {
+        vn: VirtualNodeId("node-new/00".to_owned()),
+        ni: NetworkId("node-new".to_owned()),
+    });
+    RingConfig {
+        nodes: vnodes,
+        update,
+        epoch: 1,
+        partitioner,
+        labels: Default::default(),
+    }
+}
+
+/// Runs `f` over every key for a number of rounds, and returns the fastest
+/// per-key time.
+fn measure(keys: &[(String, String)], mut f: impl FnMut(&str, &str)) -> Duration {
+    (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for (scope, key) in keys {
+                f(scope, key);
+            }
+            start.elapsed() / keys.len() as u32
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let keys: Vec<(String, String)> = (0..KEYS)
+        .map(|i| ("users".to_owned(), format!("user-{i}")))
+        .collect();
+
+    let hash_only = measure(&keys, |scope, key| {
+        black_box(CompositeKey::new(scope.to_owned(), key.to_owned()).as_sha256());
+    });
+    println!("key hash: {hash_only:?}/request");
+    println!();
+
+    println!(
+        "{:<12} {:>6} {:>10} {:>14} {:>14}",
+        "partitioner", "nodes", "vnodes", "route", "route+update"
+    );
+    for partitioner in [
+        PartitionerKind::Ring,
+        PartitionerKind::Rendezvous,
+        PartitionerKind::Jump,
+    ] {
+        for nodes in [4, 32, 256] {
+            let mut times = Vec::new();
+            for updating in [false, true] {
+                let placement = Placement::new(&ring_config(nodes, partitioner, updating));
+                times.push(measure(&keys, |scope, key| {
+                    // The same work the router does to decide where a request
+                    // goes: hash the key once, then look up its owner and
+                    // whether it is being moved.
+                    let ck = CompositeKey::new(scope.to_owned(), key.to_owned());
+                    let hash = ck.as_sha256();
+                    black_box(placement.owner(&hash));
+                    black_box(placement.moving_to(&hash));
+                }));
+            }
+            println!(
+                "{:<12} {:>6} {:>10} {:>14} {:>14}",
+                format!("{partitioner:?}"),
+                nodes,
+                nodes * VNODES_PER_NODE,
+                format!("{:?}", times[0]),
+                format!("{:?}", times[1]),
+            );
+        }
+    }
+}
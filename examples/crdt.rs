@@ -10,19 +10,11 @@ use serde::{Deserialize, Serialize};
 
 const SCOPE: &'static str = "crdt-example";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Crdt, StoredCrdt)]
 struct MyCrdt {
     value: Version<u64, HashSet<u64>>,
 }
 
-impl Crdt for MyCrdt {
-    fn merge_from(&mut self, other: Self) {
-        self.value.merge_from(other.value);
-    }
-}
-
-impl StoredCrdt for MyCrdt {}
-
 impl Default for MyCrdt {
     fn default() -> Self {
         Self {
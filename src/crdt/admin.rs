@@ -0,0 +1,345 @@
+//! Operator controls for the controller.
+//!
+//! Controls are stored by every router rather than by the controller, so that
+//! they survive controller failover and are visible to whichever controller
+//! is currently the leader. They form a CRDT: the controller reads them from
+//! all routers, merges them, and writes the result back to any router that
+//! has fallen behind.
+
+use std::collections::{BTreeMap, HashMap};
+
+use amimono::{
+    rpc::{RpcError, RpcResult},
+    runtime::{self, Location},
+};
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{
+    Crdt,
+    crdt::{Max, Version},
+    ring::{NetworkId, RingConfig, VirtualNodeId},
+    router::{CrdtRouterClient, CrdtRouterComponent},
+};
+
+/// Runtime settings for the controller, set by operators.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerControls {
+    /// When set, the controller only computes and publishes its plan.
+    pub dry_run: Version<u64, Max<bool>>,
+    /// When set, the controller does not reconcile the cluster.
+    pub paused: Version<u64, Max<bool>>,
+    /// Nodes whose virtual nodes should be removed from the ring, even though
+    /// they are still discovered.
+    pub draining: HashMap<NetworkId, Version<u64, Max<bool>>>,
+    /// The most recently requested forced bootstrap.
+    pub bootstrap: Option<ForcedBootstrap>,
+    /// The ID of the most recent forced bootstrap the controller has carried
+    /// out.
+    pub bootstrap_done: Max<u64>,
+}
+
+impl ControllerControls {
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.1.0
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.1.0
+    }
+
+    pub fn is_draining(&self, ni: &NetworkId) -> bool {
+        self.draining.get(ni).is_some_and(|x| x.1.0)
+    }
+
+    /// A forced bootstrap that has been requested but not yet carried out.
+    pub fn pending_bootstrap(&self) -> Option<&ForcedBootstrap> {
+        self.bootstrap
+            .as_ref()
+            .filter(|b| b.id > self.bootstrap_done.0)
+    }
+}
+
+impl Default for ControllerControls {
+    fn default() -> Self {
+        ControllerControls {
+            dry_run: Version(0, Max(false)),
+            paused: Version(0, Max(false)),
+            draining: HashMap::new(),
+            bootstrap: None,
+            bootstrap_done: Max(0),
+        }
+    }
+}
+
+impl Crdt for ControllerControls {
+    fn merge_from(&mut self, other: Self) {
+        self.dry_run.merge_from(other.dry_run);
+        self.paused.merge_from(other.paused);
+        self.draining.merge_from(other.draining);
+        self.bootstrap.merge_from(other.bootstrap);
+        self.bootstrap_done.merge_from(other.bootstrap_done);
+    }
+}
+
+/// A request to push a specific ring config to every node, replacing whatever
+/// they have.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForcedBootstrap {
+    pub id: u64,
+    pub ring: RingConfig,
+}
+
+impl ForcedBootstrap {
+    /// Every field of the ring, in a form with a total order.
+    fn ring_key(&self) -> impl Ord + '_ {
+        // Destructured so that a new field can't be left out by accident.
+        let RingConfig {
+            nodes,
+            update,
+            epoch,
+            partitioner,
+            labels,
+        } = &self.ring;
+        let mut nodes: Vec<_> = nodes.iter().collect();
+        nodes.sort();
+        (nodes, update, epoch, partitioner, labels)
+    }
+}
+
+/// Merge by picking the request with the larger ID. Two different requests
+/// with the same ID can only come from racing operators, in which case the
+/// larger ring, compared field by field, is picked so that every router
+/// agrees.
+impl Crdt for ForcedBootstrap {
+    fn merge_from(&mut self, other: Self) {
+        let replace = match self.id.cmp(&other.id) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Equal => self.ring_key() < other.ring_key(),
+            std::cmp::Ordering::Greater => false,
+        };
+        if replace {
+            *self = other;
+        }
+    }
+}
+
+/// The sequence of actions the controller would take to reach the desired
+/// cluster configuration, starting from what it currently knows.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControllerPlan {
+    /// Whether the controller is only planning.
+    pub dry_run: bool,
+    /// Whether the controller is paused.
+    pub paused: bool,
+    /// Human-readable descriptions of each step, in order.
+    pub steps: Vec<String>,
+    /// Set if planning stopped early, with the reason.
+    pub stopped: Option<String>,
+    /// Problems with the desired configuration that don't stop the plan.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+/// Where a key lives, as seen by the router that owns it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPlacement {
+    pub scope: String,
+    pub key: String,
+    /// The hex-encoded hash that places the key on the ring.
+    pub hash: String,
+    /// The hash tag the hash was computed from, if the key has one and its
+    /// scope uses hash tags.
+    pub hash_tag: Option<String>,
+    /// The virtual node that owns the key.
+    pub owner_vn: VirtualNodeId,
+    /// The node that owns the key.
+    pub owner: NetworkId,
+    /// The nodes that would hold copies of the key with the configured number
+    /// of replicas, starting with the owner.
+    pub replicas: Vec<NetworkId>,
+    /// Set if the key is in a range that the update in progress is moving,
+    /// to the node it is moving to.
+    pub moving_to: Option<NetworkId>,
+    /// The routers a `get` for the key passes through, ending with the owner.
+    pub path: Vec<String>,
+    /// Whether each of the owner, the node the key is moving to, and the
+    /// replicas has a copy of the key on disk, or `None` if it couldn't be
+    /// asked.
+    pub copies: BTreeMap<NetworkId, Option<bool>>,
+}
+
+async fn discover_routers() -> RpcResult<Vec<Location>> {
+    let routers = runtime::discover::<CrdtRouterComponent>()
+        .await
+        .map_err(|e| RpcError::Misc(format!("discovery failed: {e}")))?
+        .into_iter()
+        .filter(|x| matches!(x, Location::Stable(_)))
+        .collect::<Vec<_>>();
+    if routers.is_empty() {
+        return Err(RpcError::Misc("no routers".to_owned()));
+    }
+    Ok(routers)
+}
+
+/// Fetch the controls from every router and merge them. Also returns the
+/// routers whose copy differs from the merged result.
+pub(crate) async fn read_controls(
+    router: &CrdtRouterClient,
+) -> RpcResult<(ControllerControls, Vec<Location>)> {
+    let routers = discover_routers().await?;
+    let results = join_all(routers.iter().map(|loc| {
+        let router = router.at(loc.clone());
+        async move { router.get_controls().await }
+    }))
+    .await;
+
+    let mut merged: Option<ControllerControls> = None;
+    let mut copies = Vec::new();
+    for (loc, res) in routers.into_iter().zip(results) {
+        match res {
+            Ok(c) => {
+                merged = Some(match merged {
+                    Some(m) => m.merge(c.clone()),
+                    None => c.clone(),
+                });
+                copies.push((loc, c));
+            }
+            Err(e) => log::warn!("failed to get controls from {loc:?}: {e:?}"),
+        }
+    }
+
+    let merged = merged.ok_or(RpcError::Misc("no router returned controls".to_owned()))?;
+    let behind = copies
+        .into_iter()
+        .filter(|(_, c)| *c != merged)
+        .map(|(loc, _)| loc)
+        .collect();
+    Ok((merged, behind))
+}
+
+/// Write the controls to the given routers. Fails only if no router accepted
+/// the write.
+pub(crate) async fn write_controls(
+    router: &CrdtRouterClient,
+    to: Vec<Location>,
+    controls: &ControllerControls,
+) -> RpcResult<()> {
+    let results = join_all(to.iter().map(|loc| {
+        let router = router.at(loc.clone());
+        let controls = controls.clone();
+        async move { router.set_controls(controls).await }
+    }))
+    .await;
+
+    let mut accepted = 0;
+    for (loc, res) in to.iter().zip(results) {
+        match res {
+            Ok(_) => accepted += 1,
+            Err(e) => log::warn!("failed to set controls at {loc:?}: {e:?}"),
+        }
+    }
+    if accepted == 0 && !to.is_empty() {
+        return Err(RpcError::Misc("no router accepted controls".to_owned()));
+    }
+    Ok(())
+}
+
+/// An administrative handle for operating the CRDT storage cluster.
+pub struct CrdtAdmin {
+    router: CrdtRouterClient,
+}
+
+impl CrdtAdmin {
+    pub fn new() -> CrdtAdmin {
+        CrdtAdmin {
+            router: CrdtRouterClient::new(),
+        }
+    }
+
+    /// Get the current controls, merged from all routers.
+    pub async fn controls(&self) -> RpcResult<ControllerControls> {
+        read_controls(&self.router).await.map(|(c, _)| c)
+    }
+
+    async fn update_controls<F>(&self, update: F) -> RpcResult<()>
+    where
+        F: FnOnce(&mut ControllerControls),
+    {
+        let (mut controls, _) = read_controls(&self.router).await?;
+        update(&mut controls);
+        write_controls(&self.router, discover_routers().await?, &controls).await
+    }
+
+    /// Enable or disable dry-run mode. In dry-run mode the controller only
+    /// publishes the plan it would execute, which can be fetched with
+    /// [`plan`][Self::plan] or viewed on the dashboard.
+    pub async fn set_dry_run(&self, enabled: bool) -> RpcResult<()> {
+        self.update_controls(|c| {
+            c.dry_run = Version(c.dry_run.0 + 1, Max(enabled));
+        })
+        .await
+    }
+
+    /// Pause the controller. It keeps publishing its plan, but takes no
+    /// action other than carrying out forced bootstraps.
+    pub async fn pause(&self) -> RpcResult<()> {
+        self.update_controls(|c| {
+            c.paused = Version(c.paused.0 + 1, Max(true));
+        })
+        .await
+    }
+
+    /// Resume the controller after [`pause`][Self::pause].
+    pub async fn resume(&self) -> RpcResult<()> {
+        self.update_controls(|c| {
+            c.paused = Version(c.paused.0 + 1, Max(false));
+        })
+        .await
+    }
+
+    /// Mark a node as draining, or stop draining it. The virtual nodes of a
+    /// draining node are removed from the ring, and its data is moved to the
+    /// remaining nodes.
+    pub async fn set_draining(&self, ni: NetworkId, draining: bool) -> RpcResult<()> {
+        self.update_controls(|c| {
+            let version = c.draining.get(&ni).map(|x| x.0).unwrap_or(0);
+            c.draining.insert(ni, Version(version + 1, Max(draining)));
+        })
+        .await
+    }
+
+    /// Push the given ring config to every discovered node, replacing their
+    /// current config and canceling any migration in progress. Any update in
+    /// the given config is dropped. This is carried out even while the
+    /// controller is paused, but not in dry-run mode.
+    pub async fn force_bootstrap(&self, mut ring: RingConfig) -> RpcResult<()> {
+        ring.update = None;
+        self.update_controls(|c| {
+            let last = c.bootstrap.as_ref().map(|x| x.id).unwrap_or(0);
+            let id = std::cmp::max(last, c.bootstrap_done.0) + 1;
+            c.bootstrap = Some(ForcedBootstrap { id, ring });
+        })
+        .await
+    }
+
+    /// Get the most recently published controller plan.
+    pub async fn plan(&self) -> RpcResult<Option<ControllerPlan>> {
+        self.router.get_plan().await
+    }
+
+    /// Find out where a key lives, by routing the request the same way as a
+    /// `get`.
+    pub async fn explain(&self, scope: &str, key: &str) -> RpcResult<KeyPlacement> {
+        self.router
+            .explain(Vec::new(), scope.to_owned(), key.to_owned())
+            .await
+    }
+}
+
+impl Default for CrdtAdmin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,107 @@
+//! How evenly the key space is split between nodes.
+
+use std::collections::BTreeMap;
+
+use crate::crdt::{
+    partition::{KeyRange, Partitioner},
+    ring::{NetworkId, RingConfig},
+};
+
+/// The share of the key space owned by a single node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeShare {
+    pub node: NetworkId,
+    /// The number of virtual nodes assigned to the node.
+    pub vnodes: usize,
+    /// The fraction of all key hashes the node owns, between 0 and 1.
+    pub fraction: f64,
+}
+
+/// A report of how the key space is split between the nodes of a ring.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RingBalance {
+    /// Every node in the ring config, in order.
+    pub nodes: Vec<NodeShare>,
+    /// The smallest share of any node.
+    pub min: f64,
+    /// The largest share of any node.
+    pub max: f64,
+    /// The share each node would have if the split were perfectly even.
+    pub mean: f64,
+    /// The standard deviation of the shares.
+    pub stddev: f64,
+    /// The largest share relative to the mean. 1.0 is perfectly even, and
+    /// 2.0 means some node owns twice as many keys as it would in an even
+    /// split.
+    pub imbalance: f64,
+}
+
+impl RingBalance {
+    /// Compute the balance of a config's nodes under the given partitioner,
+    /// which should have been built from the config. Nodes that own no
+    /// virtual nodes are included with a share of zero.
+    pub fn new(cf: &RingConfig, partitioner: &dyn Partitioner) -> RingBalance {
+        let mut shares: BTreeMap<&NetworkId, (usize, f64)> = BTreeMap::new();
+        for ni in cf.nodes.values() {
+            shares.entry(ni).or_default().0 += 1;
+        }
+
+        let ranges = partitioner.ranges();
+        for (i, (start, vn)) in ranges.iter().enumerate() {
+            let Some(ni) = cf.network_id(vn) else {
+                continue;
+            };
+            let end = ranges[(i + 1) % ranges.len()].0;
+            let range = KeyRange { start: *start, end };
+            shares.entry(ni).or_default().1 += range.fraction();
+        }
+
+        let nodes: Vec<NodeShare> = (shares.into_iter())
+            .map(|(ni, (vnodes, fraction))| NodeShare {
+                node: ni.clone(),
+                vnodes,
+                fraction,
+            })
+            .collect();
+        RingBalance::from_shares(nodes)
+    }
+
+    /// Compute the balance of a config's nodes under its own partitioner.
+    pub fn of_config(cf: &RingConfig) -> RingBalance {
+        let partitioner = cf.partitioner.build(cf.nodes.keys().cloned());
+        RingBalance::new(cf, partitioner.as_ref())
+    }
+
+    fn from_shares(nodes: Vec<NodeShare>) -> RingBalance {
+        if nodes.is_empty() {
+            return RingBalance {
+                nodes,
+                min: 0.0,
+                max: 0.0,
+                mean: 0.0,
+                stddev: 0.0,
+                imbalance: 0.0,
+            };
+        }
+        let n = nodes.len() as f64;
+        let min = nodes
+            .iter()
+            .map(|x| x.fraction)
+            .fold(f64::INFINITY, f64::min);
+        let max = nodes.iter().map(|x| x.fraction).fold(0.0, f64::max);
+        let mean = 1.0 / n;
+        let variance = nodes
+            .iter()
+            .map(|x| (x.fraction - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        RingBalance {
+            nodes,
+            min,
+            max,
+            mean,
+            stddev: variance.sqrt(),
+            imbalance: max / mean,
+        }
+    }
+}
@@ -1,13 +1,19 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
 use amimono::rpc::{RpcError, RpcResult};
+use serde::{Serialize, de::DeserializeOwned};
 
-use crate::crdt::{StoredCrdt, check_scope, router::CrdtRouterClient};
+use crate::crdt::{
+    DeltaCrdt, StoredCrdt, check_scope,
+    crdt::{HybridClock, LwwRegister},
+    router::CrdtRouterClient,
+};
 
 /// A CRDT client bound to a particular scope.
 pub struct CrdtClient<T: StoredCrdt> {
     scope: String,
     router: CrdtRouterClient,
+    clock: Arc<HybridClock>,
     _marker: PhantomData<T>,
 }
 
@@ -22,10 +28,23 @@ impl<T: StoredCrdt> CrdtClient<T> {
         CrdtClient {
             scope: scope.to_owned(),
             router: CrdtRouterClient::new(),
+            clock: HybridClock::shared(),
             _marker: PhantomData,
         }
     }
 
+    /// Use the given clock for timestamps instead of
+    /// [`HybridClock::shared`].
+    pub fn with_clock(mut self, clock: Arc<HybridClock>) -> CrdtClient<T> {
+        self.clock = clock;
+        self
+    }
+
+    /// The clock used for timestamps.
+    pub fn clock(&self) -> &HybridClock {
+        &self.clock
+    }
+
     /// Get a value.
     pub async fn get(&self, key: &str) -> RpcResult<Option<T>> {
         let data = self
@@ -55,6 +74,31 @@ impl<T: StoredCrdt> CrdtClient<T> {
             .map_err(|e| RpcError::Misc(format!("parse failed: {e}")))?;
         Ok(res_parsed)
     }
+
+    /// Put several values atomically, and return the updated values in the
+    /// same order. Either every value is merged or none is, and readers never
+    /// see only some of them merged. All keys must be placed on the same node,
+    /// so the scope must use [hash tags][crate::crdt::ScopeOptions::hash_tags]
+    /// and all keys must have the same hash tag.
+    pub async fn put_many(&self, items: Vec<(&str, T)>) -> RpcResult<Vec<T>> {
+        let items = items
+            .into_iter()
+            .map(|(key, value)| {
+                let data = serde_json::to_vec(&value)
+                    .map_err(|e| RpcError::Misc(format!("serialize failed: {e}")))?;
+                Ok((key.to_owned(), data))
+            })
+            .collect::<RpcResult<Vec<_>>>()?;
+        let res = self
+            .router
+            .put_many(vec![], self.scope.clone(), items)
+            .await?;
+        res.into_iter()
+            .map(|x| {
+                serde_json::from_slice(&x).map_err(|e| RpcError::Misc(format!("parse failed: {e}")))
+            })
+            .collect()
+    }
 }
 
 impl<T: StoredCrdt + Default> CrdtClient<T> {
@@ -63,3 +107,30 @@ impl<T: StoredCrdt + Default> CrdtClient<T> {
         self.get(key).await.map(|x| x.unwrap_or_default())
     }
 }
+
+impl<T: StoredCrdt + DeltaCrdt> CrdtClient<T> {
+    /// Merge a delta into a value. Unlike [`put`][Self::put], the updated
+    /// value isn't sent back, so only the delta is sent over the network. A
+    /// value that doesn't exist yet starts out as the delta.
+    pub async fn put_delta(&self, key: &str, delta: T) -> RpcResult<()> {
+        let data = serde_json::to_vec(&delta)
+            .map_err(|e| RpcError::Misc(format!("serialize failed: {e}")))?;
+        self.router
+            .put_delta(vec![], self.scope.clone(), key.to_owned(), data)
+            .await
+    }
+}
+
+impl<U: Serialize + DeserializeOwned + 'static> CrdtClient<LwwRegister<U>> {
+    /// Write a value to a register, and return the updated register. The
+    /// current value is read first, so that the write wins over every write
+    /// that came before it even if the writer's clock is ahead of ours.
+    pub async fn set(&self, key: &str, value: U) -> RpcResult<LwwRegister<U>> {
+        if let Some(current) = self.get(key).await? {
+            self.clock.observe(current.timestamp());
+        }
+        let res = self.put(key, LwwRegister::new(value, &self.clock)).await?;
+        self.clock.observe(res.timestamp());
+        Ok(res)
+    }
+}
@@ -1,31 +1,64 @@
 use std::{
-    collections::{HashMap, HashSet},
-    time::Duration,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    time::{Duration, Instant},
 };
 
 use amimono::{
     config::{Binding, ComponentConfig},
     runtime::{self, Component, Location},
 };
-use futures::future::BoxFuture;
+use futures::future::{BoxFuture, join_all};
 
 use crate::crdt::{
-    ring::{HashRing, NetworkId, RingConfig, RingUpdateConfig, VirtualNodeId},
+    admin::{self, ControllerControls, ControllerPlan},
+    crdt::Max,
+    lease::{Campaign, Election},
+    membership::Membership,
+    partition::{KeyRange, PartitionerKind, Placement},
+    ring::{Labels, NetworkId, RingConfig, RingUpdateConfig, VirtualNodeId},
     router::{CrdtRouterClient, CrdtRouterComponent},
+    settings::ControllerSettings,
 };
 
 const DEFAULT_WEIGHT: usize = 16;
 
+/// Planning gives up after this many steps.
+const MAX_PLAN_STEPS: usize = 1024;
+
+/// An unchanged plan is still republished this often, so that routers that
+/// restarted or joined recently pick it up.
+const PLAN_REPUBLISH_INTERVAL: Duration = Duration::from_secs(30);
+
 type CtlResult<T> = Result<T, String>;
 
 struct DesiredConfig {
+    /// The weight of every discovered node. Draining nodes have a weight of
+    /// zero: they still get configs, but own no part of the ring.
     weight: HashMap<NetworkId, usize>,
+    /// Nodes that have stopped answering, but not for long enough to be
+    /// declared dead.
+    suspect: BTreeSet<NetworkId>,
+    /// Nodes that have been declared dead. They are never in `weight`.
+    dead: BTreeSet<NetworkId>,
+    /// The partitioner used when bootstrapping the cluster.
+    partitioner: PartitionerKind,
+    /// Labels for nodes in `weight`.
+    labels: BTreeMap<NetworkId, Labels>,
 }
 
 impl DesiredConfig {
     fn from_nodes(it: impl Iterator<Item = NetworkId>) -> DesiredConfig {
-        let weight = it.map(|loc| (loc, DEFAULT_WEIGHT)).collect();
-        DesiredConfig { weight }
+        Self::from_weights(it.map(|loc| (loc, DEFAULT_WEIGHT)).collect())
+    }
+
+    fn from_weights(weight: HashMap<NetworkId, usize>) -> DesiredConfig {
+        DesiredConfig {
+            weight,
+            suspect: BTreeSet::new(),
+            dead: BTreeSet::new(),
+            partitioner: ControllerSettings::current().partitioner,
+            labels: BTreeMap::new(),
+        }
     }
 
     fn as_ring_config(&self) -> RingConfig {
@@ -39,10 +72,25 @@ impl DesiredConfig {
         RingConfig {
             nodes,
             update: None,
+            epoch: 0,
+            partitioner: self.partitioner,
+            labels: self.labels.clone(),
         }
     }
 }
 
+/// A forced bootstrap config is given an epoch above everything known, so that
+/// reconciliation never prefers a config it was meant to replace.
+fn forced_ring(known: &HashMap<NetworkId, ActualConfig>, ring: &RingConfig) -> RingConfig {
+    RingConfig {
+        nodes: ring.nodes.clone(),
+        update: None,
+        epoch: std::cmp::max(ring.epoch, next_epoch(known)),
+        partitioner: ring.partitioner,
+        labels: ring.labels.clone(),
+    }
+}
+
 fn mk_virtual_node(NetworkId(ni): &NetworkId, i: usize) -> VirtualNodeId {
     VirtualNodeId(format!("{ni}/{i:02x}"))
 }
@@ -93,10 +141,11 @@ impl ClusterConfig {
             configs
         };
 
+        // Every node taking part in an update has a copy of it.
         let update = {
             let mut updates = configs.iter().flat_map(|x| x.ring.update.clone());
             let update = updates.next();
-            if let Some(u) = updates.next() {
+            if let Some(u) = updates.find(|u| Some(u) != update.as_ref()) {
                 Err(format!("cluster has multiple active updates: {:?}", u))?;
             }
             update
@@ -126,15 +175,28 @@ impl ClusterConfig {
         for cc in configs.iter() {
             if (cc.ring.nodes != nodes_with_update && cc.ring.nodes != nodes_without_update)
                 || (cc.ring.update != None && cc.ring.update != update)
+                || cc.ring.partitioner != first.ring.partitioner
             {
                 Err(format!("cluster has inconsistent config"))?;
             }
         }
 
+        // While a migration is being finished, nodes that already have the
+        // finished config are one epoch ahead.
+        let epoch = configs
+            .iter()
+            .filter(|cc| cc.ring.nodes == nodes_without_update)
+            .map(|cc| cc.ring.epoch)
+            .max()
+            .unwrap_or(0);
+
         Ok(ClusterConfig {
             ring: RingConfig {
                 nodes: nodes_without_update,
                 update,
+                epoch,
+                partitioner: first.ring.partitioner,
+                labels: first.ring.labels.clone(),
             },
         })
     }
@@ -144,22 +206,467 @@ impl ClusterConfig {
     }
 }
 
+/// A repair for a cluster whose nodes disagree about the ring in a way that
+/// [`ClusterConfig::parse`] can't make sense of, e.g. after a controller
+/// crashed halfway through pushing configs, or a node was restored from an
+/// old backup.
+#[derive(Clone, Debug)]
+struct Reconciliation {
+    /// The authoritative config. Its update, if any, is left running.
+    ring: RingConfig,
+    /// The nodes whose config will be replaced, with the config they have now.
+    mismatched: BTreeMap<NetworkId, RingConfig>,
+    /// Why this config was picked.
+    reason: String,
+}
+
+impl Reconciliation {
+    /// Pick an authoritative config: the one with the highest epoch, then the
+    /// one held by the most nodes. Returns `None` if nothing would change.
+    fn plan(known: &HashMap<NetworkId, ActualConfig>) -> CtlResult<Option<Reconciliation>> {
+        let configs: BTreeMap<&NetworkId, &RingConfig> = known
+            .iter()
+            .flat_map(|(ni, a)| a.as_config().map(|cf| (ni, cf)))
+            .collect();
+
+        // Group nodes by the ring they have, ignoring updates.
+        let mut groups: Vec<(&RingConfig, u64, usize)> = Vec::new();
+        for cf in configs.values() {
+            match groups.iter_mut().find(|(g, _, _)| same_ring(g, cf)) {
+                Some((_, epoch, n)) => {
+                    *epoch = std::cmp::max(*epoch, cf.epoch);
+                    *n += 1;
+                }
+                None => groups.push((cf, cf.epoch, 1)),
+            }
+        }
+        groups.sort_by(|(a, a_epoch, a_n), (b, b_epoch, b_n)| {
+            (a_epoch, a_n)
+                .cmp(&(b_epoch, b_n))
+                .then_with(|| sorted_nodes(a).cmp(&sorted_nodes(b)))
+                .then_with(|| a.partitioner.cmp(&b.partitioner))
+        });
+        let Some((chosen, epoch, n)) = groups.last().cloned() else {
+            return Ok(None);
+        };
+
+        let mut updates = Vec::new();
+        for cf in configs.values() {
+            if same_ring(cf, chosen)
+                && let Some(u) = cf.update.as_ref()
+                && !updates.contains(&u)
+            {
+                updates.push(u);
+            }
+        }
+        if updates.len() > 1 {
+            Err(format!(
+                "cannot reconcile: nodes with the chosen ring have different updates: {updates:?}"
+            ))?;
+        }
+
+        let ring = RingConfig {
+            nodes: chosen.nodes.clone(),
+            update: updates.first().cloned().cloned(),
+            epoch,
+            partitioner: chosen.partitioner,
+            labels: chosen.labels.clone(),
+        };
+        let with_update = ring.update.as_ref().map(|_| ring.updated_nodes());
+
+        let mismatched: BTreeMap<NetworkId, RingConfig> = configs
+            .into_iter()
+            .filter(|(_, cf)| {
+                let nodes_ok = (cf.nodes == ring.nodes || Some(&cf.nodes) == with_update.as_ref())
+                    && cf.partitioner == ring.partitioner;
+                let update_ok = cf.update.is_none() || cf.update == ring.update;
+                !nodes_ok || !update_ok
+            })
+            .map(|(ni, cf)| (ni.clone(), cf.clone()))
+            .collect();
+        if mismatched.is_empty() {
+            return Ok(None);
+        }
+
+        let reason = format!(
+            "highest epoch {epoch}, held by {n}/{} configured nodes",
+            known.values().filter(|x| x.is_configured()).count()
+        );
+        Ok(Some(Reconciliation {
+            ring,
+            mismatched,
+            reason,
+        }))
+    }
+
+    /// The config pushed to mismatched nodes.
+    fn repaired(&self) -> RingConfig {
+        RingConfig {
+            nodes: self.ring.nodes.clone(),
+            update: None,
+            epoch: self.ring.epoch,
+            partitioner: self.ring.partitioner,
+            labels: self.ring.labels.clone(),
+        }
+    }
+
+    /// A full description of what will change and why.
+    fn explain(&self) -> String {
+        let mut lines = vec![format!(
+            "reconciling inconsistent ring configs: picked {} virtual nodes at epoch {} ({})",
+            self.ring.nodes.len(),
+            self.ring.epoch,
+            self.reason
+        )];
+        if let Some(u) = self.ring.update.as_ref() {
+            lines.push(format!("  update {u:?} is left running"));
+        }
+        for (ni, cf) in self.mismatched.iter() {
+            let mut added: Vec<&str> = (self.ring.nodes.iter())
+                .filter(|(vn, owner)| cf.nodes.get(vn) != Some(owner))
+                .map(|(vn, _)| vn.0.as_str())
+                .collect();
+            let mut removed: Vec<&str> = (cf.nodes.iter())
+                .filter(|(vn, owner)| self.ring.nodes.get(vn) != Some(owner))
+                .map(|(vn, _)| vn.0.as_str())
+                .collect();
+            added.sort();
+            removed.sort();
+            lines.push(format!(
+                "  {}: epoch {} -> {}, +{:?} -{:?}",
+                ni.0, cf.epoch, self.ring.epoch, added, removed
+            ));
+            if let Some(u) = cf.update.as_ref() {
+                lines.push(format!("  {}: update {u:?} is dropped", ni.0));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+fn same_ring(a: &RingConfig, b: &RingConfig) -> bool {
+    a.nodes == b.nodes && a.partitioner == b.partitioner
+}
+
+fn sorted_nodes(cf: &RingConfig) -> Vec<(&VirtualNodeId, &NetworkId)> {
+    let mut nodes: Vec<_> = cf.nodes.iter().collect();
+    nodes.sort();
+    nodes
+}
+
+/// The epoch to use for a config that replaces everything currently known.
+fn next_epoch(known: &HashMap<NetworkId, ActualConfig>) -> u64 {
+    let max = known
+        .values()
+        .flat_map(|x| x.as_config())
+        .map(|cf| cf.epoch)
+        .max();
+    max.map(|x| x + 1).unwrap_or(0)
+}
+
 enum Action {
     Nothing,
     BootstrapAll,
     BootstrapOne(NetworkId, RingConfig),
     BeginAdd(ClusterConfig, VirtualNodeId, NetworkId),
     TryFinishAdd(ClusterConfig, VirtualNodeId, NetworkId),
+    BeginRemove(ClusterConfig, VirtualNodeId, NetworkId),
+    TryFinishRemove(ClusterConfig, VirtualNodeId, NetworkId),
+    Reconcile(Reconciliation),
+    Evict(ClusterConfig, BTreeSet<NetworkId>),
+    Relabel(BTreeMap<NetworkId, Labels>),
+}
+
+impl Action {
+    fn describe(&self) -> String {
+        match self {
+            Action::Nothing => "Nothing".to_owned(),
+            Action::BootstrapAll => "BootstrapAll".to_owned(),
+            Action::BootstrapOne(ni, _) => format!("BootstrapOne {}", ni.0),
+            Action::BeginAdd(_, vn, ni) => format!("BeginAdd {} -> {}", vn.0, ni.0),
+            Action::TryFinishAdd(_, vn, ni) => format!("TryFinishAdd {} -> {}", vn.0, ni.0),
+            Action::BeginRemove(_, vn, ni) => format!("BeginRemove {} <- {}", vn.0, ni.0),
+            Action::TryFinishRemove(_, vn, ni) => {
+                format!("TryFinishRemove {} <- {}", vn.0, ni.0)
+            }
+            Action::Reconcile(r) => format!(
+                "Reconcile {} nodes to epoch {}",
+                r.mismatched.len(),
+                r.ring.epoch
+            ),
+            Action::Evict(_, dead) => {
+                let dead: Vec<&str> = dead.iter().map(|ni| ni.0.as_str()).collect();
+                format!("Evict {}", dead.join(", "))
+            }
+            Action::Relabel(labels) => format!("Relabel {} nodes", labels.len()),
+        }
+    }
+}
+
+/// The config the nodes taking part in an update should be given to begin it,
+/// and those nodes: every node with keys to move, and the node named in the
+/// update.
+fn begin_update_config(
+    cc: &ClusterConfig,
+    update: RingUpdateConfig,
+) -> (BTreeSet<NetworkId>, RingConfig) {
+    let cf = RingConfig {
+        update: Some(update),
+        ..cc.ring.clone()
+    };
+
+    let mut participants = Placement::new(&cf).sources();
+    match cf.update.as_ref() {
+        Some(RingUpdateConfig::ToAdd { ni, .. }) => participants.insert(ni.clone()),
+        Some(RingUpdateConfig::ToRemove { ni, .. }) => participants.insert(ni.clone()),
+        None => false,
+    };
+
+    (participants, cf)
+}
+
+/// The config every node should have once an update is done.
+fn finish_update_config(cc: &ClusterConfig, update: RingUpdateConfig) -> RingConfig {
+    let cf = RingConfig {
+        update: Some(update),
+        ..cc.ring.clone()
+    };
+    RingConfig {
+        nodes: cf.updated_nodes(),
+        update: None,
+        epoch: cc.ring.epoch + 1,
+        partitioner: cc.ring.partitioner,
+        labels: cc.ring.labels.clone(),
+    }
+}
+
+fn to_add(vn: &VirtualNodeId, ni: &NetworkId) -> RingUpdateConfig {
+    RingUpdateConfig::ToAdd {
+        vn: vn.clone(),
+        ni: ni.clone(),
+    }
+}
+
+fn to_remove(vn: &VirtualNodeId, ni: &NetworkId) -> RingUpdateConfig {
+    RingUpdateConfig::ToRemove {
+        vn: vn.clone(),
+        ni: ni.clone(),
+    }
+}
+
+/// Check whether the replicas of every key could be spread across failure
+/// domains as configured, under the given config.
+fn spread_warnings(cf: &RingConfig, settings: &ControllerSettings) -> Vec<String> {
+    let n = settings.replicas;
+    if n <= 1 {
+        return Vec::new();
+    }
+
+    let mut res = Vec::new();
+    let owners: BTreeSet<&NetworkId> = cf.nodes.values().collect();
+    if owners.len() < n {
+        res.push(format!("only {} nodes for {n} replicas", owners.len()));
+    }
+    for label in settings.spread_by.iter() {
+        let values: BTreeSet<&String> = (owners.iter())
+            .flat_map(|ni| cf.labels.get(*ni).and_then(|x| x.get(label)))
+            .collect();
+        if values.len() < n {
+            res.push(format!(
+                "only {} values of {label:?} for {n} replicas",
+                values.len()
+            ));
+        }
+    }
+
+    let placement = Placement::new(cf);
+    let ranges = placement.ranges();
+    let unmet: f64 = (0..ranges.len())
+        .filter(|i| {
+            let (start, _) = &ranges[*i];
+            placement.replicas(start, n, &settings.spread_by).unmet
+        })
+        .map(|i| {
+            let end = ranges[(i + 1) % ranges.len()].0;
+            KeyRange {
+                start: ranges[i].0,
+                end,
+            }
+            .fraction()
+        })
+        .sum();
+    if unmet > 0.0 {
+        res.push(format!(
+            "replicas of {:.1}% of keys can't be spread by {:?}",
+            unmet * 100.0,
+            settings.spread_by
+        ));
+    }
+
+    res
+}
+
+/// A duration picked uniformly from within half of `d` in either direction.
+fn jittered(d: Duration) -> Duration {
+    d.mul_f64(rand::random_range(0.5..1.5))
+}
+
+/// The nodes a ring config refers to, either as owners of virtual nodes or as
+/// the target of an update.
+fn involved_nodes(cf: &RingConfig) -> BTreeSet<NetworkId> {
+    let mut res: BTreeSet<NetworkId> = cf.nodes.values().cloned().collect();
+    match cf.update.as_ref() {
+        Some(RingUpdateConfig::ToAdd { ni, .. }) => res.insert(ni.clone()),
+        Some(RingUpdateConfig::ToRemove { ni, .. }) => res.insert(ni.clone()),
+        None => false,
+    };
+    res
+}
+
+/// The config that results from removing dead nodes from the ring. The keys
+/// owned by a dead node are reassigned to the surviving virtual nodes.
+///
+/// Storage is not replicated, so the only surviving copies of a dead node's
+/// data are those it was in the middle of migrating. A migration from a dead
+/// node to a live one is finished, so that the live node keeps what it has
+/// already received. Any other migration is canceled, and the live nodes are
+/// asked to rehome whatever the canceled migration left misplaced.
+fn evict_config(cc: &ClusterConfig, dead: &BTreeSet<NetworkId>) -> CtlResult<RingConfig> {
+    let mut cf = RingConfig {
+        nodes: cc.ring.nodes.clone(),
+        update: None,
+        epoch: cc.ring.epoch + 1,
+        partitioner: cc.ring.partitioner,
+        labels: cc.ring.labels.clone(),
+    };
+
+    if let Some(RingUpdateConfig::ToAdd { vn, ni }) = cc.ring.update.as_ref() {
+        let sources = Placement::new(&cc.ring).sources();
+        if !sources.is_disjoint(dead) && !dead.contains(ni) {
+            cf.nodes.insert(vn.clone(), ni.clone());
+        }
+    }
+
+    cf.nodes.retain(|_, ni| !dead.contains(ni));
+    if cf.nodes.is_empty() {
+        Err("every node in the ring is dead! refusing to empty the ring")?;
+    }
+
+    Ok(cf)
+}
+
+/// The result of pinging nodes.
+struct Health {
+    suspect: BTreeSet<NetworkId>,
+    dead: BTreeSet<NetworkId>,
+    /// Nodes that answered with a different boot ID than last time.
+    restarted: BTreeSet<NetworkId>,
+    /// Nodes that answered for the first time since they were last forgotten,
+    /// e.g. because they were evicted, or since the controller started.
+    joined: BTreeSet<NetworkId>,
+}
+
+/// Tracks when each node the controller cares about last answered it, and
+/// which process answered.
+struct FailureDetector {
+    last_seen: HashMap<NetworkId, Instant>,
+    boot_ids: HashMap<NetworkId, String>,
+    suspect: BTreeSet<NetworkId>,
+}
+
+impl FailureDetector {
+    fn new() -> FailureDetector {
+        FailureDetector {
+            last_seen: HashMap::new(),
+            boot_ids: HashMap::new(),
+            suspect: BTreeSet::new(),
+        }
+    }
+
+    /// Ping every node and sort out the ones that need attention. A node seen
+    /// for the first time gets a full timeout before it can be suspected.
+    async fn check(
+        &mut self,
+        router: &CrdtRouterClient,
+        nodes: BTreeSet<NetworkId>,
+        settings: &ControllerSettings,
+    ) -> Health {
+        let results = join_all(nodes.iter().map(|ni| {
+            let router = router.at(ni.as_location());
+            async move { router.ping().await }
+        }))
+        .await;
+
+        let now = Instant::now();
+        let mut restarted = BTreeSet::new();
+        let mut joined = BTreeSet::new();
+        self.last_seen.retain(|ni, _| nodes.contains(ni));
+        self.boot_ids.retain(|ni, _| nodes.contains(ni));
+        for (ni, res) in nodes.iter().zip(results) {
+            match res {
+                Ok(boot_id) => {
+                    self.last_seen.insert(ni.clone(), now);
+                    let old = self.boot_ids.insert(ni.clone(), boot_id.clone());
+                    match old {
+                        None => joined.insert(ni.clone()),
+                        Some(old) if old != boot_id => restarted.insert(ni.clone()),
+                        Some(_) => false,
+                    };
+                }
+                Err(e) => {
+                    log::debug!("ping {ni:?} failed: {e:?}");
+                    self.last_seen.entry(ni.clone()).or_insert(now);
+                }
+            }
+        }
+
+        let mut suspect = BTreeSet::new();
+        let mut dead = BTreeSet::new();
+        for (ni, seen) in self.last_seen.iter() {
+            let silent = now - *seen;
+            if settings.dead_after.is_some_and(|x| silent >= x) {
+                dead.insert(ni.clone());
+            } else if silent >= settings.suspect_after {
+                suspect.insert(ni.clone());
+            }
+        }
+
+        for ni in suspect.difference(&self.suspect) {
+            log::warn!("{ni:?} is suspected to have failed");
+        }
+        for ni in self.suspect.difference(&suspect) {
+            if !dead.contains(ni) {
+                log::info!("{ni:?} is answering again");
+            }
+        }
+        self.suspect = suspect.clone();
+
+        Health {
+            suspect,
+            dead,
+            restarted,
+            joined,
+        }
+    }
 }
 
 enum NextIter {
     Fast,
     Wait,
+    Standby,
 }
 
 struct Controller {
     known: HashMap<NetworkId, ActualConfig>,
     router: CrdtRouterClient,
+    published: Option<(ControllerPlan, Instant)>,
+    /// When each entry in `known` should next be re-read from its node.
+    refresh_at: HashMap<NetworkId, Instant>,
+    detector: FailureDetector,
+    /// Nodes that still need to rehome keys left behind by an eviction.
+    rehome: BTreeSet<NetworkId>,
+    /// When our lease runs out, as of the last campaign.
+    leader_until: Option<Instant>,
 }
 
 impl Controller {
@@ -167,14 +674,29 @@ impl Controller {
         Controller {
             known: HashMap::new(),
             router: CrdtRouterClient::new(),
+            published: None,
+            refresh_at: HashMap::new(),
+            detector: FailureDetector::new(),
+            rehome: BTreeSet::new(),
+            leader_until: None,
+        }
+    }
+
+    /// Check that we still hold the lease. Called before every RPC that
+    /// changes the cluster, since an iteration can outlast the lease.
+    fn check_lease(&self) -> CtlResult<()> {
+        match self.leader_until {
+            Some(t) if Instant::now() < t => Ok(()),
+            _ => Err("lease expired".to_string()),
         }
     }
 
     async fn push_config_force(&mut self, to: &NetworkId, cf: RingConfig) -> CtlResult<()> {
+        self.check_lease()?;
         match self.router.at(to.as_location()).set_ring(cf.clone()).await {
             Ok(_) => {
                 log::info!("updated config at {to:?}");
-                self.known.insert(to.clone(), ActualConfig::Configured(cf));
+                self.remember(to, ActualConfig::Configured(cf));
                 Ok(())
             }
             Err(e) => {
@@ -185,6 +707,14 @@ impl Controller {
         }
     }
 
+    /// Record a node's config as known, and schedule it to be re-read.
+    fn remember(&mut self, ni: &NetworkId, actual: ActualConfig) {
+        let interval = ControllerSettings::current().refresh_interval;
+        self.known.insert(ni.clone(), actual);
+        self.refresh_at
+            .insert(ni.clone(), Instant::now() + jittered(interval));
+    }
+
     async fn push_config(&mut self, to: &NetworkId, cf: RingConfig) -> CtlResult<()> {
         if self.known.get(to).and_then(|x| x.as_config()) == Some(&cf) {
             log::debug!("skipping push_config for {to:?}: known config matches");
@@ -194,7 +724,65 @@ impl Controller {
         }
     }
 
-    async fn get_desired_config(&self) -> CtlResult<DesiredConfig> {
+    async fn get_desired_config(&self, controls: &ControllerControls) -> CtlResult<DesiredConfig> {
+        let settings = ControllerSettings::current();
+        let membership = &settings.membership;
+        let mut manifest_labels = BTreeMap::new();
+        let mut desired = match membership.load().await {
+            Ok(Some(manifest)) => {
+                if manifest.nodes.is_empty() {
+                    Err("membership manifest is empty! cannot calculate desired config")?;
+                }
+                manifest_labels = manifest.labels;
+                DesiredConfig::from_weights(manifest.nodes.into_iter().collect())
+            }
+            Ok(None) => match membership {
+                Membership::Discovery => self.get_discovered_config().await?,
+                _ => self.get_bootstrap_config().await?,
+            },
+            Err(e) => match membership {
+                Membership::Stored { .. } => {
+                    log::debug!("{e}");
+                    self.get_bootstrap_config().await?
+                }
+                _ => Err(e)?,
+            },
+        };
+        for (ni, w) in desired.weight.iter_mut() {
+            if controls.is_draining(ni) {
+                *w = 0;
+            }
+        }
+        for ni in desired.weight.keys() {
+            let labels = manifest_labels.get(ni).or_else(|| settings.labels.get(ni));
+            if let Some(labels) = labels {
+                desired.labels.insert(ni.clone(), labels.clone());
+            }
+        }
+        Ok(desired)
+    }
+
+    /// A stored manifest can't be read until the ring exists, so a cluster
+    /// without a manifest is bootstrapped from discovery. Once any node has a
+    /// config, the manifest is required.
+    async fn get_bootstrap_config(&self) -> CtlResult<DesiredConfig> {
+        let desired = self.get_discovered_config().await?;
+        for ni in desired.weight.keys() {
+            let ring = self
+                .router
+                .at(ni.as_location())
+                .get_ring()
+                .await
+                .map_err(|e| format!("failed to get ring at {ni:?}: {e:?}"))?;
+            if ring.is_some() {
+                Err("no membership manifest! refusing to change the ring")?;
+            }
+        }
+        log::warn!("no membership manifest: bootstrapping from discovered routers");
+        Ok(desired)
+    }
+
+    async fn get_discovered_config(&self) -> CtlResult<DesiredConfig> {
         let routers = runtime::discover::<CrdtRouterComponent>()
             .await
             .map_err(|_| "failed to discover routers")?
@@ -213,10 +801,53 @@ impl Controller {
         Ok(DesiredConfig::from_nodes(routers.into_iter()))
     }
 
+    /// Ping every node that is discovered or in the ring, and record which
+    /// ones have stopped answering. Dead nodes are forgotten.
+    async fn update_health(&mut self, desired: &mut DesiredConfig) {
+        let mut nodes: BTreeSet<NetworkId> = desired.weight.keys().cloned().collect();
+        for cf in self.known.values().flat_map(|x| x.as_config()) {
+            nodes.extend(involved_nodes(cf));
+        }
+
+        let settings = ControllerSettings::current();
+        let health = self
+            .detector
+            .check(&self.router, nodes.clone(), &settings)
+            .await;
+
+        // A restarted node may have come back with an old ring config, e.g.
+        // restored from a backup, so whatever we knew about it is stale.
+        for ni in health.restarted.iter() {
+            if self.known.remove(ni).is_some() {
+                log::info!("{ni:?} restarted: refetching its config");
+            }
+        }
+
+        for ni in health.dead.iter() {
+            desired.weight.remove(ni);
+        }
+        let dead = &health.dead;
+        self.known
+            .retain(|ni, _| nodes.contains(ni) && !dead.contains(ni));
+        self.refresh_at.retain(|ni, _| self.known.contains_key(ni));
+        // A node that comes back after being evicted, or restarts partway
+        // through a migration, may still hold keys that it no longer owns.
+        self.rehome
+            .extend(health.joined.iter().chain(&health.restarted).cloned());
+        self.rehome
+            .retain(|ni| nodes.contains(ni) && !dead.contains(ni));
+        desired.suspect = health.suspect;
+        desired.dead = health.dead;
+    }
+
     async fn update_actual_config(&mut self, desired: &DesiredConfig) -> CtlResult<()> {
+        let now = Instant::now();
         for router in desired.weight.keys() {
-            if self.known.contains_key(&router) {
+            let due = self.refresh_at.get(router).is_none_or(|t| *t <= now);
+            if self.known.contains_key(router) && !due {
                 log::debug!("skipping {router:?}: config already known");
+            } else if desired.suspect.contains(router) {
+                log::debug!("skipping {router:?}: suspect");
             } else {
                 log::debug!("fetching config from {router:?}");
                 let ring = self
@@ -229,24 +860,34 @@ impl Controller {
                     Some(r) => ActualConfig::Configured(r),
                     None => ActualConfig::Unconfigured,
                 };
-                self.known.insert(router.clone(), actual);
+                if let Some(old) = self.known.get(router)
+                    && *old != actual
+                {
+                    log::warn!("config at {router:?} changed behind our back: {actual:?}");
+                }
+                self.remember(router, actual);
             }
         }
         Ok(())
     }
 
     fn action(&self, desired: &DesiredConfig) -> CtlResult<Action> {
+        Self::action_for(&self.known, desired)
+    }
+
+    fn action_for(
+        known: &HashMap<NetworkId, ActualConfig>,
+        desired: &DesiredConfig,
+    ) -> CtlResult<Action> {
         use Action::*;
 
-        let configured: Vec<NetworkId> = self
-            .known
+        let configured: Vec<NetworkId> = known
             .iter()
             .filter(|(_, cf)| cf.is_configured())
             .map(|(ni, _)| ni)
             .cloned()
             .collect();
-        let unconfigured: Vec<NetworkId> = self
-            .known
+        let unconfigured: BTreeSet<NetworkId> = known
             .iter()
             .filter(|(_, cf)| cf.is_unconfigured())
             .map(|(ni, _)| ni)
@@ -261,14 +902,37 @@ impl Controller {
         // Otherwise we have to decide on a course of action. The first thing
         // we'll do is verify that the current state is something we can work
         // with, by consolidating all the various configs.
-        let cc = ClusterConfig::parse(&self.known)?;
+        let cc = match ClusterConfig::parse(known) {
+            Ok(cc) => cc,
+            Err(e) => match Reconciliation::plan(known)? {
+                Some(r) => return Ok(Reconcile(r)),
+                None => Err(e)?,
+            },
+        };
+
+        // Dead nodes are removed from the ring before anything else, since
+        // their ranges are unavailable until then.
+        let dead: BTreeSet<NetworkId> = involved_nodes(&cc.ring)
+            .intersection(&desired.dead)
+            .cloned()
+            .collect();
+        if !dead.is_empty() {
+            return Ok(Evict(cc, dead));
+        }
+
+        // Nothing else is changed while a node might be failing, since any
+        // migration involving it would stall.
+        if !desired.suspect.is_empty() {
+            log::debug!("waiting on suspect nodes {:?}", desired.suspect);
+            return Ok(Nothing);
+        }
 
         // First we check if there are any unconfigured nodes. If so, we'll
         // simply bootstrap them.
         if let Some(ni) = unconfigured.iter().next() {
             let ring = RingConfig {
-                nodes: cc.ring.nodes.clone(),
                 update: None,
+                ..cc.ring.clone()
             };
             return Ok(BootstrapOne(ni.clone(), ring));
         }
@@ -279,13 +943,21 @@ impl Controller {
             use RingUpdateConfig::*;
             let action = match u {
                 ToAdd { vn, ni } => TryFinishAdd(cc, vn, ni),
-                ToRemove { .. } => todo!(),
+                ToRemove { vn, ni } => TryFinishRemove(cc, vn, ni),
             };
             return Ok(action);
         }
 
+        // Labels don't affect ownership, so they can be changed in place.
+        let relabel = (known.values())
+            .flat_map(|x| x.as_config())
+            .any(|cf| cf.labels != desired.labels);
+        if relabel {
+            return Ok(Relabel(desired.labels.clone()));
+        }
+
         // If not, we'll check if there's anything we can start doing.
-        if let Some(action) = self.action_to_start(cc, desired)? {
+        if let Some(action) = Self::action_to_start(cc, desired)? {
             return Ok(action);
         }
 
@@ -293,22 +965,187 @@ impl Controller {
         Ok(Nothing)
     }
 
-    fn action_to_start(
-        &self,
-        cc: ClusterConfig,
-        desired: &DesiredConfig,
-    ) -> CtlResult<Option<Action>> {
-        let target = desired.as_ring_config();
+    fn action_to_start(cc: ClusterConfig, desired: &DesiredConfig) -> CtlResult<Option<Action>> {
+        // Nodes are visited in a fixed order so that a published plan matches
+        // what is actually executed.
+        let target: BTreeMap<_, _> = desired.as_ring_config().nodes.into_iter().collect();
+        let current: BTreeMap<_, _> = cc.ring.nodes.clone().into_iter().collect();
 
-        for (vn, ni) in target.nodes.into_iter() {
-            if !cc.ring.nodes.contains_key(&vn) {
-                return Ok(Some(Action::BeginAdd(cc, vn, ni)));
+        if target.is_empty() {
+            Err("every node is draining! refusing to empty the ring")?;
+        }
+
+        // Additions go first, so that capacity is added before it's taken
+        // away when a node is being replaced.
+        for (vn, ni) in target.iter() {
+            if !current.contains_key(vn) {
+                return Ok(Some(Action::BeginAdd(cc, vn.clone(), ni.clone())));
+            }
+        }
+
+        for (vn, ni) in current.into_iter() {
+            if !target.contains_key(&vn) {
+                return Ok(Some(Action::BeginRemove(cc, vn, ni)));
             }
         }
 
         Ok(None)
     }
 
+    /// Compute the sequence of actions that would be taken from the current
+    /// known state, assuming every action succeeds.
+    fn plan(&self, desired: &DesiredConfig, controls: &ControllerControls) -> ControllerPlan {
+        let mut known = self.known.clone();
+        let mut steps = Vec::new();
+        let mut stopped = None;
+
+        if let Some(fb) = controls.pending_bootstrap() {
+            steps.push(format!("ForceBootstrap #{}", fb.id));
+            let ring = forced_ring(&known, &fb.ring);
+            for ni in desired.weight.keys() {
+                known.insert(ni.clone(), ActualConfig::Configured(ring.clone()));
+            }
+        }
+
+        if controls.is_paused() {
+            stopped = Some("controller is paused".to_owned());
+        }
+
+        while stopped.is_none() && steps.len() < MAX_PLAN_STEPS {
+            let action = match Self::action_for(&known, desired) {
+                Ok(Action::Nothing) => break,
+                Ok(x) => x,
+                Err(e) => {
+                    stopped = Some(e);
+                    break;
+                }
+            };
+            steps.push(action.describe());
+            if let Err(e) = Self::simulate(&mut known, desired, &action) {
+                stopped = Some(e);
+                break;
+            }
+        }
+        if steps.len() >= MAX_PLAN_STEPS {
+            stopped = Some(format!("plan truncated at {MAX_PLAN_STEPS} steps"));
+        }
+
+        ControllerPlan {
+            dry_run: controls.is_dry_run(),
+            paused: controls.is_paused(),
+            steps,
+            stopped,
+            warnings: spread_warnings(&desired.as_ring_config(), &ControllerSettings::current()),
+        }
+    }
+
+    /// Apply the effects of an action to a known state without contacting any
+    /// nodes. This must agree with what the `do_*` methods push.
+    fn simulate(
+        known: &mut HashMap<NetworkId, ActualConfig>,
+        desired: &DesiredConfig,
+        action: &Action,
+    ) -> CtlResult<()> {
+        match action {
+            Action::Nothing => {}
+            Action::BootstrapAll => {
+                let cf = desired.as_ring_config();
+                for ni in desired.weight.keys() {
+                    known.insert(ni.clone(), ActualConfig::Configured(cf.clone()));
+                }
+            }
+            Action::BootstrapOne(ni, ring) => {
+                known.insert(ni.clone(), ActualConfig::Configured(ring.clone()));
+            }
+            Action::BeginAdd(cc, vn, ni) => {
+                let (participants, cf) = begin_update_config(cc, to_add(vn, ni));
+                for ni in participants {
+                    known.insert(ni, ActualConfig::Configured(cf.clone()));
+                }
+            }
+            Action::TryFinishAdd(cc, vn, ni) => {
+                let cf = finish_update_config(cc, to_add(vn, ni));
+                for actual in known.values_mut() {
+                    *actual = ActualConfig::Configured(cf.clone());
+                }
+            }
+            Action::BeginRemove(cc, vn, ni) => {
+                let (participants, cf) = begin_update_config(cc, to_remove(vn, ni));
+                for ni in participants {
+                    known.insert(ni, ActualConfig::Configured(cf.clone()));
+                }
+            }
+            Action::TryFinishRemove(cc, vn, ni) => {
+                let cf = finish_update_config(cc, to_remove(vn, ni));
+                for actual in known.values_mut() {
+                    *actual = ActualConfig::Configured(cf.clone());
+                }
+            }
+            Action::Reconcile(r) => {
+                for ni in r.mismatched.keys() {
+                    known.insert(ni.clone(), ActualConfig::Configured(r.repaired()));
+                }
+            }
+            Action::Evict(cc, dead) => {
+                let cf = evict_config(cc, dead)?;
+                for actual in known.values_mut() {
+                    if actual.is_configured() {
+                        *actual = ActualConfig::Configured(cf.clone());
+                    }
+                }
+            }
+            Action::Relabel(labels) => {
+                for actual in known.values_mut() {
+                    if let ActualConfig::Configured(cf) = actual {
+                        cf.labels = labels.clone();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn publish_plan(&mut self, desired: &DesiredConfig, plan: ControllerPlan) {
+        if let Some((last, at)) = self.published.as_ref()
+            && *last == plan
+            && at.elapsed() < PLAN_REPUBLISH_INTERVAL
+        {
+            return;
+        }
+        if let Err(e) = self.check_lease() {
+            log::debug!("not publishing plan: {e}");
+            return;
+        }
+
+        log::debug!("publishing plan with {} steps", plan.steps.len());
+        let results = join_all(desired.weight.keys().map(|ni| {
+            let router = self.router.at(ni.as_location());
+            let plan = plan.clone();
+            async move { router.set_plan(plan).await }
+        }))
+        .await;
+        for (ni, res) in desired.weight.keys().zip(results) {
+            if let Err(e) = res {
+                log::debug!("failed to publish plan to {ni:?}: {e:?}");
+            }
+        }
+
+        self.published = Some((plan, Instant::now()));
+    }
+
+    async fn sync_controls(&mut self) -> CtlResult<ControllerControls> {
+        let (controls, behind) = admin::read_controls(&self.router)
+            .await
+            .map_err(|e| format!("failed to read controls: {e:?}"))?;
+        if !behind.is_empty() && self.check_lease().is_ok() {
+            log::debug!("writing controls back to {} routers", behind.len());
+            if let Err(e) = admin::write_controls(&self.router, behind, &controls).await {
+                log::warn!("failed to write back controls: {e:?}");
+            }
+        }
+        Ok(controls)
+    }
+
     async fn do_bootstrap_all(&mut self, desired: &DesiredConfig) -> CtlResult<()> {
         let cf = desired.as_ring_config();
 
@@ -323,79 +1160,235 @@ impl Controller {
         Ok(())
     }
 
-    async fn do_begin_add(
-        &mut self,
-        cc: ClusterConfig,
-        vn: VirtualNodeId,
-        ni: NetworkId,
-    ) -> CtlResult<()> {
-        let ring = HashRing::from_nodes(cc.ring.nodes.keys().cloned());
-
-        let old_vn = ring.cursor(&vn).get();
-        let old_ni = cc.ring.network_id(&old_vn).ok_or("no ni for target vn")?;
-
-        let cf = RingConfig {
-            nodes: cc.ring.nodes.clone(),
-            update: Some(RingUpdateConfig::ToAdd { vn, ni }),
-        };
-
-        self.push_config(old_ni, cf).await
+    async fn do_begin(&mut self, cc: ClusterConfig, update: RingUpdateConfig) -> CtlResult<()> {
+        let (participants, cf) = begin_update_config(&cc, update);
+        for ni in participants.iter() {
+            self.push_config(ni, cf.clone()).await?;
+        }
+        Ok(())
     }
 
-    async fn do_try_finish_add(
+    /// Once every node taking part in an update is done, push the finished
+    /// config to every other node first, and then to the participants.
+    async fn do_try_finish(
         &mut self,
         cc: ClusterConfig,
-        vn: VirtualNodeId,
-        ni: NetworkId,
+        update: RingUpdateConfig,
     ) -> CtlResult<NextIter> {
-        let ring = HashRing::from_nodes(cc.ring.nodes.keys().cloned());
-
-        let old_vn = ring.cursor(&vn).get();
-        let old_ni = cc.ring.network_id(&old_vn).ok_or("no ni for target vn")?;
-
-        let updating = self
-            .router
-            .at(old_ni.as_location())
-            .updating()
-            .await
-            .map_err(|e| format!("failed to check if {old_ni:?} is updating: {e:?}"))?;
-
-        if updating {
-            log::debug!("{old_ni:?} still updating...");
-            return Ok(NextIter::Wait);
+        // A previous attempt to begin the update may have stopped partway, so
+        // make sure every participant has it before checking on them.
+        let (participants, begin) = begin_update_config(&cc, update.clone());
+        for ni in participants.iter() {
+            self.push_config(ni, begin.clone()).await?;
         }
 
-        let cf = {
-            let mut cf = RingConfig {
-                nodes: cc.ring.nodes.clone(),
-                update: None,
-            };
-            cf.nodes.insert(vn, ni);
-            cf
-        };
+        for ni in participants.iter() {
+            let updating = self
+                .router
+                .at(ni.as_location())
+                .updating()
+                .await
+                .map_err(|e| format!("failed to check if {ni:?} is updating: {e:?}"))?;
+            if updating {
+                log::debug!("{ni:?} still updating...");
+                return Ok(NextIter::Wait);
+            }
+        }
 
+        let cf = finish_update_config(&cc, update);
         let others = self
             .known
             .keys()
-            .filter(|x| *x != old_ni)
+            .filter(|x| !participants.contains(*x))
             .cloned()
             .collect::<HashSet<NetworkId>>();
 
         for ni in others.iter() {
             self.push_config(ni, cf.clone()).await?;
         }
-        self.push_config(old_ni, cf).await?;
+        for ni in participants.iter() {
+            self.push_config(ni, cf.clone()).await?;
+        }
 
         Ok(NextIter::Fast)
     }
 
+    async fn do_force_bootstrap(
+        &mut self,
+        desired: &DesiredConfig,
+        controls: &ControllerControls,
+    ) -> CtlResult<()> {
+        let Some(fb) = controls.pending_bootstrap() else {
+            return Ok(());
+        };
+
+        // Every node is attempted even if some fail, but the bootstrap is only
+        // marked as done once all of them have succeeded.
+        let ring = forced_ring(&self.known, &fb.ring);
+        let mut failed = Vec::new();
+        for ni in desired.weight.keys() {
+            if self.push_config_force(ni, ring.clone()).await.is_err() {
+                failed.push(ni.clone());
+            }
+        }
+        if !failed.is_empty() {
+            Err(format!("forced bootstrap #{} failed at {failed:?}", fb.id))?;
+        }
+
+        self.check_lease()?;
+        let mut done = controls.clone();
+        done.bootstrap_done = Max(fb.id);
+        let to = desired.weight.keys().map(|ni| ni.as_location()).collect();
+        admin::write_controls(&self.router, to, &done)
+            .await
+            .map_err(|e| format!("failed to mark bootstrap #{} done: {e:?}", fb.id))?;
+
+        Ok(())
+    }
+
+    async fn do_reconcile(&mut self, r: Reconciliation) -> CtlResult<()> {
+        log::warn!("{}", r.explain());
+
+        // Check every configured node, not just the mismatched ones: a node
+        // that agrees with the chosen ring may still hold data moved there by
+        // a migration that is about to be dropped.
+        let configured: Vec<NetworkId> = (self.known.iter())
+            .filter(|(_, cf)| cf.is_configured())
+            .map(|(ni, _)| ni.clone())
+            .collect();
+        let mut orphans = Vec::new();
+        for ni in configured.iter() {
+            let n = self
+                .router
+                .at(ni.as_location())
+                .count_orphans(r.ring.clone())
+                .await
+                .map_err(|e| format!("failed to count orphans at {ni:?}: {e:?}"))?;
+            if n > 0 {
+                orphans.push((ni.0.as_str(), n));
+            }
+        }
+        if !orphans.is_empty() {
+            Err(format!(
+                "refusing to reconcile: keys would be orphaned at {orphans:?}. \
+                 move the data or force a bootstrap"
+            ))?;
+        }
+
+        for ni in r.mismatched.keys() {
+            self.push_config_force(ni, r.repaired()).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn do_evict(&mut self, cc: ClusterConfig, dead: BTreeSet<NetworkId>) -> CtlResult<()> {
+        let cf = evict_config(&cc, &dead)?;
+        for ni in dead.iter() {
+            let n = cc.ring.nodes.values().filter(|x| *x == ni).count();
+            log::error!(
+                "{ni:?} declared dead: reassigning its {n} virtual nodes. \
+                 data stored only on it is lost"
+            );
+        }
+
+        let live: Vec<NetworkId> = (self.known.iter())
+            .filter(|(_, cf)| cf.is_configured())
+            .map(|(ni, _)| ni.clone())
+            .collect();
+        for ni in live.iter() {
+            self.push_config_force(ni, cf.clone()).await?;
+        }
+        self.rehome.extend(live);
+
+        Ok(())
+    }
+
+    async fn do_relabel(&mut self, labels: BTreeMap<NetworkId, Labels>) -> CtlResult<()> {
+        let configs: Vec<(NetworkId, RingConfig)> = (self.known.iter())
+            .flat_map(|(ni, x)| x.as_config().map(|cf| (ni.clone(), cf.clone())))
+            .collect();
+        for (ni, cf) in configs {
+            let cf = RingConfig {
+                labels: labels.clone(),
+                ..cf
+            };
+            self.push_config(&ni, cf).await?;
+        }
+        Ok(())
+    }
+
+    /// Ask nodes to send misplaced keys to their owners. Nodes that fail, or
+    /// whose config is behind the newest one we know of, are retried on the
+    /// next iteration.
+    async fn do_rehome(&mut self) {
+        let epoch_of = |ni: &NetworkId| {
+            (self.known.get(ni))
+                .and_then(|x| x.as_config())
+                .map(|cf| cf.epoch)
+        };
+        let newest = (self.known.keys()).filter_map(epoch_of).max();
+        for ni in std::mem::take(&mut self.rehome) {
+            if self.check_lease().is_err() || epoch_of(&ni) != newest {
+                self.rehome.insert(ni);
+                continue;
+            }
+            match self.router.at(ni.as_location()).rehome().await {
+                Ok(0) => {}
+                Ok(n) => log::info!("{ni:?} rehomed {n} keys"),
+                Err(e) => {
+                    log::warn!("failed to rehome keys at {ni:?}: {e:?}");
+                    self.rehome.insert(ni);
+                }
+            }
+        }
+    }
+
     async fn run_once(&mut self) -> CtlResult<NextIter> {
+        log::debug!("reading controls");
+        let controls = self.sync_controls().await?;
+
         log::debug!("getting desired config");
-        let desired = self.get_desired_config().await?;
+        let mut desired = self.get_desired_config(&controls).await?;
+
+        log::debug!("checking node health");
+        self.update_health(&mut desired).await;
 
         log::debug!("updating actual config");
         self.update_actual_config(&desired).await?;
 
+        let plan = self.plan(&desired, &controls);
+        if self
+            .published
+            .as_ref()
+            .is_none_or(|(p, _)| p.warnings != plan.warnings)
+        {
+            for w in plan.warnings.iter() {
+                log::warn!("placement constraint can't be met: {w}");
+            }
+        }
+        self.publish_plan(&desired, plan).await;
+
+        if controls.is_dry_run() {
+            log::debug!("dry run: not executing plan");
+            return Ok(NextIter::Wait);
+        }
+
+        if let Some(fb) = controls.pending_bootstrap() {
+            log::info!("forced bootstrap #{}", fb.id);
+            self.do_force_bootstrap(&desired, &controls).await?;
+            return Ok(NextIter::Fast);
+        }
+
+        if controls.is_paused() {
+            log::debug!("paused: not executing plan");
+            return Ok(NextIter::Wait);
+        }
+
+        if !self.rehome.is_empty() {
+            self.do_rehome().await;
+        }
+
         match self.action(&desired)? {
             Action::Nothing => {
                 log::debug!("nothing to do");
@@ -413,12 +1406,35 @@ impl Controller {
             }
             Action::BeginAdd(cc, vn, ni) => {
                 log::info!("starting to-add {vn:?} -> {ni:?}");
-                self.do_begin_add(cc, vn, ni).await?;
+                self.do_begin(cc, to_add(&vn, &ni)).await?;
                 Ok(NextIter::Fast)
             }
             Action::TryFinishAdd(cc, vn, ni) => {
                 log::debug!("checking to-add {vn:?} -> {ni:?}");
-                self.do_try_finish_add(cc, vn, ni).await
+                self.do_try_finish(cc, to_add(&vn, &ni)).await
+            }
+            Action::BeginRemove(cc, vn, ni) => {
+                log::info!("starting to-remove {vn:?} <- {ni:?}");
+                self.do_begin(cc, to_remove(&vn, &ni)).await?;
+                Ok(NextIter::Fast)
+            }
+            Action::TryFinishRemove(cc, vn, ni) => {
+                log::debug!("checking to-remove {vn:?} <- {ni:?}");
+                self.do_try_finish(cc, to_remove(&vn, &ni)).await
+            }
+            Action::Reconcile(r) => {
+                self.do_reconcile(r).await?;
+                Ok(NextIter::Fast)
+            }
+            Action::Evict(cc, dead) => {
+                log::warn!("evicting dead nodes {dead:?}");
+                self.do_evict(cc, dead).await?;
+                Ok(NextIter::Fast)
+            }
+            Action::Relabel(labels) => {
+                log::info!("updating node labels");
+                self.do_relabel(labels).await?;
+                Ok(NextIter::Fast)
             }
         }
     }
@@ -426,17 +1442,31 @@ impl Controller {
     fn main() -> BoxFuture<'static, ()> {
         Box::pin(async {
             let mut controller = Controller::new();
+            let mut election = Election::new();
             loop {
-                let iter = match controller.run_once().await {
-                    Ok(x) => x,
-                    Err(e) => {
-                        log::warn!("controller iter failed: {e}");
-                        NextIter::Wait
+                let iter = match election.campaign().await {
+                    Campaign::Standby => NextIter::Standby,
+                    campaign => {
+                        controller.leader_until = election.leader_until();
+                        if let Campaign::Elected = campaign {
+                            // Whatever we knew before is stale. Rebuild our
+                            // view of the cluster from the nodes themselves.
+                            controller.known.clear();
+                            controller.detector = FailureDetector::new();
+                        }
+                        match controller.run_once().await {
+                            Ok(x) => x,
+                            Err(e) => {
+                                log::warn!("controller iter failed: {e}");
+                                NextIter::Wait
+                            }
+                        }
                     }
                 };
                 let delay = match iter {
                     NextIter::Fast => Duration::from_millis(100),
                     NextIter::Wait => Duration::from_secs(5),
+                    NextIter::Standby => Duration::from_secs(1),
                 };
                 tokio::time::sleep(delay).await;
             }
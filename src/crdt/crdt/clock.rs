@@ -0,0 +1,86 @@
+use std::{
+    sync::{Arc, LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::crdt::ReplicaId;
+
+/// A hybrid logical clock timestamp.
+///
+/// Timestamps are ordered by wall clock time, then by a logical counter that
+/// orders events within the same millisecond, or after the clock has been
+/// moved forward by a timestamp from a replica whose clock is ahead. The
+/// replica ID breaks ties between replicas.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub wall_ms: u64,
+    pub logical: u32,
+    pub replica: ReplicaId,
+}
+
+/// A hybrid logical clock.
+///
+/// Every timestamp from [`now`][Self::now] is greater than every timestamp
+/// the clock has produced or [observed][Self::observe] before, even if the
+/// system clock goes backwards, while staying close to the system clock.
+/// Clients should share a clock, usually the one returned by
+/// [`shared`][Self::shared], so that their writes are ordered.
+#[derive(Debug)]
+pub struct HybridClock {
+    replica: ReplicaId,
+    last: Mutex<(u64, u32)>,
+}
+
+static SHARED: LazyLock<Arc<HybridClock>> = LazyLock::new(|| {
+    let replica = ReplicaId(format!("{:016x}", rand::random::<u64>()));
+    Arc::new(HybridClock::new(replica))
+});
+
+impl HybridClock {
+    pub fn new(replica: ReplicaId) -> HybridClock {
+        HybridClock {
+            replica,
+            last: Mutex::new((0, 0)),
+        }
+    }
+
+    /// The clock shared by this process, with a replica ID chosen randomly
+    /// when it is first used.
+    pub fn shared() -> Arc<HybridClock> {
+        SHARED.clone()
+    }
+
+    pub fn replica(&self) -> &ReplicaId {
+        &self.replica
+    }
+
+    /// A new timestamp.
+    pub fn now(&self) -> Timestamp {
+        let wall_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64)
+            .unwrap_or(0);
+        let mut last = self.last.lock().expect("failed to get clock lock");
+        *last = if wall_ms > last.0 {
+            (wall_ms, 0)
+        } else {
+            (last.0, last.1 + 1)
+        };
+        Timestamp {
+            wall_ms: last.0,
+            logical: last.1,
+            replica: self.replica.clone(),
+        }
+    }
+
+    /// Move the clock forward past a timestamp seen from elsewhere, so that
+    /// later timestamps from this clock are greater than it.
+    pub fn observe(&self, ts: &Timestamp) {
+        let mut last = self.last.lock().expect("failed to get clock lock");
+        if (ts.wall_ms, ts.logical) > *last {
+            *last = (ts.wall_ms, ts.logical);
+        }
+    }
+}
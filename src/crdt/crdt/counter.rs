@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{Crdt, DeltaCrdt, StoredCrdt, crdt::ReplicaId};
+
+/// A counter that can only be incremented.
+///
+/// Each replica counts its own increments, and the value is the sum of all of
+/// them. Merging takes the larger count for each replica, so a replica must
+/// always write a counter that includes all of its earlier increments, e.g.
+/// by incrementing the counter it last read or wrote rather than a new one.
+/// Counts and the total saturate at [`u64::MAX`] rather than overflowing.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{GCounter, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = GCounter::new();
+/// x.increment(&a, 2);
+/// let mut y = x.clone();
+/// x.increment(&a, 1);
+/// y.increment(&b, 5);
+///
+/// assert_eq!(x.clone().merge(y.clone()).value(), 8);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GCounter {
+    counts: BTreeMap<ReplicaId, u64>,
+}
+
+impl GCounter {
+    pub fn new() -> GCounter {
+        GCounter::default()
+    }
+
+    /// Add to the count of a replica, and return the delta, which holds the
+    /// replica's new count.
+    pub fn increment(&mut self, replica: &ReplicaId, by: u64) -> GCounter {
+        let count = self.counts.entry(replica.clone()).or_insert(0);
+        *count = count.saturating_add(by);
+        GCounter {
+            counts: BTreeMap::from([(replica.clone(), *count)]),
+        }
+    }
+
+    /// The total of all increments.
+    pub fn value(&self) -> u64 {
+        (self.counts.values()).fold(0, |total, x| total.saturating_add(*x))
+    }
+
+    /// The total of a single replica's increments.
+    pub fn get(&self, replica: &ReplicaId) -> u64 {
+        self.counts.get(replica).copied().unwrap_or(0)
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge_from(&mut self, other: Self) {
+        for (replica, that) in other.counts {
+            let this = self.counts.entry(replica).or_insert(0);
+            *this = std::cmp::max(*this, that);
+        }
+    }
+}
+
+impl StoredCrdt for GCounter {}
+
+impl DeltaCrdt for GCounter {
+    fn delta_since(&self, old: &Self) -> Self {
+        let counts = (self.counts.iter())
+            .filter(|(replica, x)| **x > old.get(replica))
+            .map(|(replica, x)| (replica.clone(), *x))
+            .collect();
+        GCounter { counts }
+    }
+}
+
+/// A counter that can be incremented and decremented.
+///
+/// This is a pair of [`GCounter`]s, one for increments and one for
+/// decrements, and the same rules apply to writing it. Values saturate at
+/// the bounds of [`i64`].
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{PNCounter, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = PNCounter::new();
+/// x.increment(&a, 2);
+/// let mut y = x.clone();
+/// x.decrement(&a, 5);
+/// y.increment(&b, 1);
+///
+/// assert_eq!(x.clone().merge(y.clone()).value(), -2);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PNCounter {
+    #[serde(rename = "p")]
+    increments: GCounter,
+    #[serde(rename = "n")]
+    decrements: GCounter,
+}
+
+impl PNCounter {
+    pub fn new() -> PNCounter {
+        PNCounter::default()
+    }
+
+    /// Add to the count of a replica, and return the delta.
+    pub fn increment(&mut self, replica: &ReplicaId, by: u64) -> PNCounter {
+        PNCounter {
+            increments: self.increments.increment(replica, by),
+            decrements: GCounter::new(),
+        }
+    }
+
+    /// Subtract from the count of a replica, and return the delta.
+    pub fn decrement(&mut self, replica: &ReplicaId, by: u64) -> PNCounter {
+        PNCounter {
+            increments: GCounter::new(),
+            decrements: self.decrements.increment(replica, by),
+        }
+    }
+
+    /// The total of all increments minus the total of all decrements.
+    pub fn value(&self) -> i64 {
+        saturating_difference(self.increments.value(), self.decrements.value())
+    }
+
+    /// A single replica's increments minus its decrements.
+    pub fn get(&self, replica: &ReplicaId) -> i64 {
+        saturating_difference(self.increments.get(replica), self.decrements.get(replica))
+    }
+}
+
+fn saturating_difference(a: u64, b: u64) -> i64 {
+    let x = a as i128 - b as i128;
+    x.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+impl Crdt for PNCounter {
+    fn merge_from(&mut self, other: Self) {
+        self.increments.merge_from(other.increments);
+        self.decrements.merge_from(other.decrements);
+    }
+}
+
+impl StoredCrdt for PNCounter {}
+
+impl DeltaCrdt for PNCounter {
+    fn delta_since(&self, old: &Self) -> Self {
+        PNCounter {
+            increments: self.increments.delta_since(&old.increments),
+            decrements: self.decrements.delta_since(&old.decrements),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::crdt::testing::check_replica_laws;
+
+    #[test]
+    fn gcounter_laws() {
+        let mut base = GCounter::new();
+        base.increment(&ReplicaId::new("a"), 2);
+        base.increment(&ReplicaId::new("b"), 3);
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..3) {
+                x.increment(replica, rng.random_range(1..5));
+            }
+        });
+    }
+
+    #[test]
+    fn pncounter_laws() {
+        let mut base = PNCounter::new();
+        base.increment(&ReplicaId::new("a"), 2);
+        base.decrement(&ReplicaId::new("b"), 3);
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..3) {
+                if rng.random_bool(0.5) {
+                    x.increment(replica, rng.random_range(1..5));
+                } else {
+                    x.decrement(replica, rng.random_range(1..5));
+                }
+            }
+        });
+    }
+}
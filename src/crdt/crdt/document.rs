@@ -0,0 +1,479 @@
+use std::{cmp::Ordering, fmt};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::crdt::{
+    Crdt, DeltaCrdt, StoredCrdt,
+    crdt::{MvRegister, OrMap, PNCounter, ReplicaId, Sequence},
+};
+
+/// A JSON document, made of nested maps, lists, registers, and counters.
+///
+/// The document is edited by [updating][Self::update] it to a new
+/// [`Value`]: the changes from the current value are found and made to the
+/// nested CRDTs, so that a merge keeps concurrent edits to different parts of
+/// the document. Objects are [`OrMap`]s, arrays are [`Sequence`]s, and other
+/// values are [`MvRegister`]s. A value concurrently set to two different
+/// values keeps both, and shows the one with the greater [`Dot`], which
+/// [`conflicts`][Self::conflicts] reports. Numbers that should be added to
+/// concurrently are kept in counters, made by [`increment`][Self::increment].
+///
+/// Every object, array, and other value set by an update is created with a
+/// random ID, and is only merged with copies of itself. If two replicas
+/// concurrently put something new in the same place, such as an object at a
+/// key that had a string, the one with the greater ID wins, and the other is
+/// dropped. Counters, and the objects that increments create along their
+/// paths, have no ID, so the ones created concurrently in the same place are
+/// merged. The top level of a document is always an object.
+///
+/// Every replica must make its changes to the latest value it has read or
+/// written, as with [`GCounter`][crate::crdt::crdt::GCounter].
+///
+/// [`Dot`]: crate::crdt::crdt::Dot
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{Document, DocumentError, ReplicaId}};
+/// use serde_json::json;
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = Document::from_value(&json!({"title": "Notes", "tags": ["work"]}), &a)?;
+/// let mut y = x.clone();
+///
+/// // concurrent edits to different parts of the document are all kept
+/// x.update(&json!({"title": "Meeting notes", "tags": ["work"]}), &a)?;
+/// y.update(&json!({"title": "Notes", "tags": ["work", "urgent"], "done": false}), &b)?;
+/// x.increment(&["views"], 1, &a)?;
+/// y.increment(&["views"], 2, &b)?;
+/// assert_eq!(
+///     x.clone().merge(y.clone()).to_value(),
+///     json!({"title": "Meeting notes", "tags": ["work", "urgent"], "done": false, "views": 3}),
+/// );
+///
+/// // a value written concurrently keeps both writes
+/// x.update(&json!({"title": "Plans", "tags": ["work"], "views": 1}), &a)?;
+/// y.update(&json!({"title": "Ideas", "tags": ["work"], "views": 2}), &b)?;
+/// let z = x.merge(y);
+/// assert_eq!(z.conflicts(&["title"]), [json!("Plans"), json!("Ideas")]);
+///
+/// // changes that don't fit the document are refused
+/// let mut w = z.clone();
+/// assert_eq!(w.update(&json!(["not", "an", "object"]), &a), Err(DocumentError::NotAnObject));
+/// assert_eq!(
+///     w.increment(&["tags", "5"], 1, &a),
+///     Err(DocumentError::BadIndex("5".to_owned())),
+/// );
+/// assert_eq!(w, z);
+/// # Ok::<(), DocumentError>(())
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Document {
+    root: OrMap<String, Node>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Node {
+    /// A random ID, chosen when the node is created, or zero for nodes made
+    /// by increments. The default node is a placeholder that is always
+    /// replaced.
+    #[serde(rename = "i")]
+    id: u64,
+    #[serde(flatten)]
+    kind: Kind,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Kind {
+    #[serde(rename = "v")]
+    Value(MvRegister<Value>),
+    #[serde(rename = "c")]
+    Counter(PNCounter),
+    #[serde(rename = "l")]
+    List(Sequence<Node>),
+    #[serde(rename = "m")]
+    Map(OrMap<String, Node>),
+}
+
+impl Kind {
+    fn rank(&self) -> u8 {
+        match self {
+            Kind::Value(_) => 0,
+            Kind::Counter(_) => 1,
+            Kind::List(_) => 2,
+            Kind::Map(_) => 3,
+        }
+    }
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Kind::Value(MvRegister::new())
+    }
+}
+
+impl Document {
+    pub fn new() -> Document {
+        Document::default()
+    }
+
+    /// A document holding a value, which must be an object.
+    pub fn from_value(value: &Value, replica: &ReplicaId) -> Result<Document, DocumentError> {
+        let mut res = Document::new();
+        res.update(value, replica)?;
+        Ok(res)
+    }
+
+    /// Change the document to hold a value, which must be an object, and
+    /// return the delta.
+    pub fn update(
+        &mut self,
+        value: &Value,
+        replica: &ReplicaId,
+    ) -> Result<Document, DocumentError> {
+        let Value::Object(object) = value else {
+            return Err(DocumentError::NotAnObject);
+        };
+        Ok(Document {
+            root: update_map(&mut self.root, object, replica),
+        })
+    }
+
+    /// Add to the counter at a path of object keys and array indexes, and
+    /// return the delta. A counter starting from zero replaces whatever else
+    /// is at the path, and missing objects along the path are created.
+    ///
+    /// Fails without changing anything if the path is empty, or a key along it
+    /// that is in an array isn't an index in bounds.
+    pub fn increment(
+        &mut self,
+        path: &[&str],
+        by: i64,
+        replica: &ReplicaId,
+    ) -> Result<Document, DocumentError> {
+        let Some((key, rest)) = path.split_first() else {
+            return Err(DocumentError::EmptyPath);
+        };
+        self.check_indexes(path)?;
+        Ok(Document {
+            root: (self.root).update(key.to_string(), replica, |x| x.increment(rest, by, replica)),
+        })
+    }
+
+    /// Check that every key along a path that is in an array is an index in
+    /// bounds, so that an increment along it can't fail partway through.
+    fn check_indexes(&self, path: &[&str]) -> Result<(), DocumentError> {
+        let Some((key, rest)) = path.split_first() else {
+            return Ok(());
+        };
+        let mut node = self.root.get(&key.to_string());
+        for segment in rest {
+            let Some(current) = node else {
+                break;
+            };
+            node = match &current.kind {
+                Kind::Map(map) => map.get(&segment.to_string()),
+                Kind::List(list) => match segment.parse().ok().and_then(|i| list.get(i)) {
+                    Some(x) => Some(x),
+                    None => return Err(DocumentError::BadIndex(segment.to_string())),
+                },
+                // Anything else is replaced by an object.
+                _ => None,
+            };
+        }
+        Ok(())
+    }
+
+    /// The value the document holds.
+    pub fn to_value(&self) -> Value {
+        map_to_value(&self.root)
+    }
+
+    /// Every value concurrently written at a path, or nothing if there is no
+    /// value there, or it is an object, array, or counter.
+    pub fn conflicts(&self, path: &[&str]) -> Vec<Value> {
+        let Some((key, rest)) = path.split_first() else {
+            return Vec::new();
+        };
+        let Some(mut node) = self.root.get(&key.to_string()) else {
+            return Vec::new();
+        };
+        for segment in rest {
+            let next = match &node.kind {
+                Kind::Map(map) => map.get(&segment.to_string()),
+                Kind::List(list) => segment.parse().ok().and_then(|i| list.get(i)),
+                _ => None,
+            };
+            match next {
+                Some(x) => node = x,
+                None => return Vec::new(),
+            }
+        }
+        match &node.kind {
+            Kind::Value(register) => register.values().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A change that can't be made to a [`Document`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DocumentError {
+    /// The value of a document must be an object.
+    NotAnObject,
+    /// An increment needs a path to a counter.
+    EmptyPath,
+    /// A key along a path is in an array, but isn't an index in bounds.
+    BadIndex(String),
+}
+
+impl fmt::Display for DocumentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DocumentError::NotAnObject => f.write_str("document value must be an object"),
+            DocumentError::EmptyPath => f.write_str("path is empty"),
+            DocumentError::BadIndex(x) => write!(f, "{x:?} is not an index in bounds"),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+impl Node {
+    fn from_value(value: &Value, replica: &ReplicaId) -> Node {
+        let kind = match value {
+            Value::Object(_) => Kind::Map(OrMap::new()),
+            Value::Array(_) => Kind::List(Sequence::new()),
+            _ => Kind::Value(MvRegister::new()),
+        };
+        let mut res = Node {
+            id: rand::random(),
+            kind,
+        };
+        res.update(value, replica);
+        res
+    }
+
+    /// Whether the value can be stored by updating this node in place.
+    fn holds(&self, value: &Value) -> bool {
+        match (&self.kind, value) {
+            (Kind::Map(_), Value::Object(_)) => true,
+            (Kind::List(_), Value::Array(_)) => true,
+            (Kind::Counter(_), Value::Number(n)) => n.is_i64(),
+            (Kind::Value(_), Value::Object(_) | Value::Array(_)) => false,
+            (Kind::Value(_), _) => true,
+            _ => false,
+        }
+    }
+
+    fn update(&mut self, value: &Value, replica: &ReplicaId) {
+        if !self.holds(value) {
+            *self = Node::from_value(value, replica);
+            return;
+        }
+        match (&mut self.kind, value) {
+            (Kind::Map(map), Value::Object(object)) => {
+                update_map(map, object, replica);
+            }
+            (Kind::List(list), Value::Array(array)) => update_list(list, array, replica),
+            (Kind::Counter(counter), Value::Number(n)) => {
+                // Both are i64s, so the difference always fits in an i128.
+                let by = n.as_i64().unwrap() as i128 - counter.value() as i128;
+                add(counter, by, replica);
+            }
+            (Kind::Value(register), value) => {
+                if register.get() != Some(value) {
+                    register.set(value.clone(), replica);
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn increment(&mut self, path: &[&str], by: i64, replica: &ReplicaId) {
+        let Some((segment, rest)) = path.split_first() else {
+            if !matches!(self.kind, Kind::Counter(_)) {
+                *self = Node {
+                    id: 0,
+                    kind: Kind::Counter(PNCounter::new()),
+                };
+            }
+            if let Kind::Counter(counter) = &mut self.kind {
+                add(counter, by.into(), replica);
+            }
+            return;
+        };
+        if !matches!(self.kind, Kind::Map(_) | Kind::List(_)) {
+            *self = Node {
+                id: 0,
+                kind: Kind::Map(OrMap::new()),
+            };
+        }
+        match &mut self.kind {
+            Kind::Map(map) => {
+                map.update(segment.to_string(), replica, |x| {
+                    x.increment(rest, by, replica)
+                });
+            }
+            Kind::List(list) => {
+                let index = segment.parse().expect("index was checked");
+                list.update_with(index, |x| x.increment(rest, by, replica));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match &self.kind {
+            Kind::Value(register) => (register.siblings())
+                .max_by(|a, b| a.0.cmp(b.0))
+                .map(|(_, x)| x.clone())
+                .unwrap_or(Value::Null),
+            Kind::Counter(counter) => Value::from(counter.value()),
+            Kind::List(list) => Value::Array(list.iter().map(Node::to_value).collect()),
+            Kind::Map(map) => map_to_value(map),
+        }
+    }
+}
+
+/// Merge copies of the same node, or pick the node with the greater ID. Nodes
+/// without an ID that are of different kinds are picked between by kind.
+impl Crdt for Node {
+    fn merge_from(&mut self, other: Self) {
+        match (self.id, self.kind.rank()).cmp(&(other.id, other.kind.rank())) {
+            Ordering::Less => *self = other,
+            Ordering::Greater => (),
+            Ordering::Equal => match (&mut self.kind, other.kind) {
+                (Kind::Value(this), Kind::Value(that)) => this.merge_from(that),
+                (Kind::Counter(this), Kind::Counter(that)) => this.merge_from(that),
+                (Kind::List(this), Kind::List(that)) => this.merge_with(that, Node::merge_from),
+                (Kind::Map(this), Kind::Map(that)) => this.merge_from(that),
+                _ => unreachable!(),
+            },
+        }
+    }
+}
+
+/// Add to a counter. `by` must be the difference of two i64s, so that its
+/// magnitude fits in a u64. The counter's totals saturate, so one that has
+/// been moved across most of the range of i64 may stop short of a value.
+fn add(counter: &mut PNCounter, by: i128, replica: &ReplicaId) {
+    if by > 0 {
+        counter.increment(replica, by as u64);
+    } else if by < 0 {
+        counter.decrement(replica, by.unsigned_abs() as u64);
+    }
+}
+
+fn map_to_value(map: &OrMap<String, Node>) -> Value {
+    Value::Object(map.iter().map(|(k, x)| (k.clone(), x.to_value())).collect())
+}
+
+/// Update a map to hold an object, and return the delta. Keys that already
+/// hold their new value are left alone.
+fn update_map(
+    map: &mut OrMap<String, Node>,
+    object: &Map<String, Value>,
+    replica: &ReplicaId,
+) -> OrMap<String, Node> {
+    let mut delta = OrMap::new();
+    let removed: Vec<String> = (map.keys())
+        .filter(|k| !object.contains_key(*k))
+        .cloned()
+        .collect();
+    for key in removed {
+        delta.merge_from(map.remove(&key));
+    }
+    for (key, value) in object {
+        let existing = map.get(key);
+        if existing.is_some_and(|x| x.to_value() == *value) {
+            continue;
+        }
+        let update = match existing {
+            Some(_) => map.update(key.clone(), replica, |x| x.update(value, replica)),
+            None => map.update(key.clone(), replica, |x| {
+                *x = Node::from_value(value, replica)
+            }),
+        };
+        delta.merge_from(update);
+    }
+    delta
+}
+
+/// Update a list to hold an array. The values that are the same at the start
+/// and end of both are left alone, and the values between them are updated in
+/// place where they can be, and otherwise replaced.
+fn update_list(list: &mut Sequence<Node>, array: &[Value], replica: &ReplicaId) {
+    let current: Vec<Value> = list.iter().map(Node::to_value).collect();
+    let prefix = (current.iter().zip(array))
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = (current[prefix..].iter().rev())
+        .zip(array[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (
+        prefix..current.len() - suffix,
+        &array[prefix..array.len() - suffix],
+    );
+
+    let paired = std::cmp::min(old.len(), new.len());
+    for (i, value) in new[..paired].iter().enumerate() {
+        let index = prefix + i;
+        if list.get(index).unwrap().holds(value) {
+            list.update_with(index, |x| x.update(value, replica));
+        } else {
+            list.remove(index);
+            list.insert(index, Node::from_value(value, replica), replica);
+        }
+    }
+    list.remove_range(prefix + paired..old.end);
+    let added = new[paired..].iter().map(|x| Node::from_value(x, replica));
+    list.insert_all(prefix + paired, added, replica);
+}
+
+impl Crdt for Document {
+    fn merge_from(&mut self, other: Self) {
+        self.root.merge_from(other.root);
+    }
+}
+
+impl StoredCrdt for Document {}
+
+impl DeltaCrdt for Document {}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+    use serde_json::json;
+
+    use super::*;
+    use crate::crdt::testing::check_replica_laws;
+
+    #[test]
+    fn document_laws() {
+        let base = Document::from_value(
+            &json!({"a": 1, "b": [1, 2], "c": {}}),
+            &ReplicaId::new("base"),
+        )
+        .unwrap();
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..3) {
+                let key = ["a", "b", "c"][rng.random_range(0..3)];
+                let n = rng.random_range(0..10);
+                let mut value = x.to_value();
+                value[key] = match rng.random_range(0..4) {
+                    0 => json!(n),
+                    1 => json!([n, 1, 2]),
+                    2 => json!({"n": n}),
+                    _ => json!({}),
+                };
+                x.update(&value, replica).unwrap();
+                if rng.random_bool(0.3) {
+                    x.increment(&["d"], 1, replica).unwrap();
+                }
+            }
+        });
+    }
+}
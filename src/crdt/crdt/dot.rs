@@ -0,0 +1,118 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{
+    Crdt,
+    crdt::{GCounter, ReplicaId},
+};
+
+/// A unique tag for a change, made of the replica that made it and a counter
+/// that the replica increments for every change.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dot(pub ReplicaId, pub u64);
+
+/// The dots a replica has seen, as the highest counter seen from each replica.
+/// Dots from a replica are made in order, so this covers every one of them up
+/// to that counter. Merging takes the highest counter for each replica.
+pub type VersionVector = GCounter;
+
+/// The dots a value has seen: a [`VersionVector`], plus the dots past it that
+/// were seen out of order.
+///
+/// A replica's own values always see its dots in order, but a delta only
+/// sees the dots it supersedes and the dot of its change, so merging a delta
+/// can leave gaps. Once the gaps are filled the dots are folded into the
+/// version vector.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalContext {
+    #[serde(rename = "v")]
+    versions: VersionVector,
+    #[serde(rename = "d", default, skip_serializing_if = "BTreeSet::is_empty")]
+    cloud: BTreeSet<Dot>,
+}
+
+impl CausalContext {
+    pub fn new() -> CausalContext {
+        CausalContext::default()
+    }
+
+    /// Whether a dot has been seen.
+    pub fn covers(&self, dot: &Dot) -> bool {
+        self.versions.get(&dot.0) >= dot.1 || self.cloud.contains(dot)
+    }
+
+    /// Make a new dot for a change by a replica, and record it as seen.
+    pub(crate) fn next_dot(&mut self, replica: &ReplicaId) -> Dot {
+        let past = (self.cloud.iter())
+            .filter(|x| x.0 == *replica)
+            .map(|x| x.1)
+            .max();
+        let counter = std::cmp::max(self.versions.get(replica), past.unwrap_or(0));
+        let dot = Dot(replica.clone(), counter + 1);
+        self.insert(dot.clone());
+        dot
+    }
+
+    /// Record a dot as seen.
+    pub(crate) fn insert(&mut self, dot: Dot) {
+        self.cloud.insert(dot);
+        self.compact();
+    }
+
+    /// Fold the dots that continue the version vector into it. The cloud is
+    /// sorted by replica and then counter, so a run of dots is folded in one
+    /// pass.
+    fn compact(&mut self) {
+        for dot in std::mem::take(&mut self.cloud) {
+            let seen = self.versions.get(&dot.0);
+            if dot.1 == seen + 1 {
+                self.versions.increment(&dot.0, 1);
+            } else if dot.1 > seen {
+                self.cloud.insert(dot);
+            }
+        }
+    }
+}
+
+impl FromIterator<Dot> for CausalContext {
+    fn from_iter<I: IntoIterator<Item = Dot>>(iter: I) -> Self {
+        let mut context = CausalContext {
+            versions: VersionVector::new(),
+            cloud: iter.into_iter().collect(),
+        };
+        context.compact();
+        context
+    }
+}
+
+impl Crdt for CausalContext {
+    fn merge_from(&mut self, other: Self) {
+        self.versions.merge_from(other.versions);
+        self.cloud.extend(other.cloud);
+        self.compact();
+    }
+}
+
+/// Merge two sets of dots, keeping the dots both sides have, and the dots
+/// one side has that the other side hasn't seen. A dot that one side has
+/// seen but no longer has was superseded there, and is dropped.
+pub(crate) fn merge_dots<D>(
+    this: BTreeMap<Dot, D>,
+    this_cx: &CausalContext,
+    mut that: BTreeMap<Dot, D>,
+    that_cx: &CausalContext,
+) -> BTreeMap<Dot, D> {
+    let mut res = BTreeMap::new();
+    for (dot, x) in this {
+        if that.remove(&dot).is_some() || !that_cx.covers(&dot) {
+            res.insert(dot, x);
+        }
+    }
+    for (dot, x) in that {
+        if !this_cx.covers(&dot) {
+            res.insert(dot, x);
+        }
+    }
+    res
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+
+use crate::crdt::{
+    Crdt, DeltaCrdt, StoredCrdt,
+    crdt::{
+        ReplicaId,
+        dot::{CausalContext, Dot, merge_dots},
+    },
+};
+
+/// A map that supports removing keys, with values that are themselves CRDTs.
+///
+/// Every update of a key is tagged with a [`Dot`], and records the value the
+/// update left the key with. Removing a key removes the updates of it that the
+/// remover has seen, so an update concurrent with a remove keeps the key. The
+/// value of a key is the merge of the values of its remaining updates, so a
+/// key that is updated after it was removed starts over from the default
+/// value.
+///
+/// Every replica must make its changes to the latest value it has read or
+/// written, as with [`GCounter`][crate::crdt::crdt::GCounter].
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{GCounter, OrMap, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x: OrMap<&str, GCounter> = OrMap::new();
+/// x.update("visits", &a, |c| c.increment(&a, 1));
+/// x.update("likes", &a, |c| c.increment(&a, 1));
+/// let mut y = x.clone();
+/// let mut z = x.clone();
+///
+/// // concurrently, a removes the likes and b increments them
+/// x.remove(&"likes");
+/// y.update("likes", &b, |c| c.increment(&b, 2));
+/// assert_eq!(x.clone().merge(y.clone()).get(&"likes").unwrap().value(), 3);
+///
+/// // values merge
+/// let c = ReplicaId::new("c");
+/// z.update("visits", &c, |x| x.increment(&c, 5));
+/// assert_eq!(y.clone().merge(z.clone()).get(&"visits").unwrap().value(), 6);
+///
+/// // a removed key starts over when it is updated again
+/// x.update("likes", &a, |c| c.increment(&a, 1));
+/// assert_eq!(x.get(&"likes").unwrap().value(), 1);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrMap<K, V> {
+    context: CausalContext,
+    entries: BTreeMap<K, Entry<V>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Entry<V> {
+    /// The updates of the key that haven't been superseded, with the value
+    /// each of them left the key with.
+    dots: BTreeMap<Dot, V>,
+    /// The merge of the values of the updates.
+    value: V,
+}
+
+impl<V: Crdt + Clone> Entry<V> {
+    fn from_dots(dots: BTreeMap<Dot, V>) -> Option<Entry<V>> {
+        let value = dots.values().cloned().reduce(V::merge)?;
+        Some(Entry { dots, value })
+    }
+}
+
+impl<K, V> Default for OrMap<K, V> {
+    fn default() -> Self {
+        OrMap {
+            context: CausalContext::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V> OrMap<K, V> {
+    pub fn new() -> OrMap<K, V> {
+        OrMap::default()
+    }
+
+    /// Update the value of a key, starting from the default value if the key
+    /// isn't in the map, and return the delta. The delta holds the whole new
+    /// value of the key.
+    pub fn update<F, R>(&mut self, key: K, replica: &ReplicaId, update: F) -> OrMap<K, V>
+    where
+        K: Clone,
+        V: Default + Clone,
+        F: FnOnce(&mut V) -> R,
+    {
+        let dot = self.context.next_dot(replica);
+        let (superseded, mut value) = match self.entries.remove(&key) {
+            Some(x) => (x.dots.into_keys().collect(), x.value),
+            None => (Vec::new(), V::default()),
+        };
+        update(&mut value);
+        let dots = BTreeMap::from([(dot.clone(), value.clone())]);
+        let entry = Entry { dots, value };
+        self.entries.insert(key.clone(), entry.clone());
+        OrMap {
+            context: superseded.into_iter().chain(Some(dot)).collect(),
+            entries: BTreeMap::from([(key, entry)]),
+        }
+    }
+
+    /// Merge a value into the value of a key, and return the delta.
+    pub fn insert(&mut self, key: K, value: V, replica: &ReplicaId) -> OrMap<K, V>
+    where
+        K: Clone,
+        V: Crdt + Default + Clone,
+    {
+        self.update(key, replica, |x| x.merge_from(value))
+    }
+
+    /// Remove a key, along with every update of it this copy of the map has
+    /// seen, and return the delta.
+    pub fn remove(&mut self, key: &K) -> OrMap<K, V> {
+        let superseded = self.entries.remove(key).into_iter();
+        OrMap {
+            context: superseded.flat_map(|x| x.dots.into_keys()).collect(),
+            entries: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|x| &x.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// The keys and values in the map, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, x)| (k, &x.value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K: Ord, V: Crdt + Clone> Crdt for OrMap<K, V> {
+    fn merge_from(&mut self, other: Self) {
+        let mut this = std::mem::take(&mut self.entries);
+        for (key, that) in other.entries {
+            let dots = this.remove(&key).map(|x| x.dots).unwrap_or_default();
+            let dots = merge_dots(dots, &self.context, that.dots, &other.context);
+            if let Some(entry) = Entry::from_dots(dots) {
+                self.entries.insert(key, entry);
+            }
+        }
+        for (key, entry) in this {
+            let dots = merge_dots(entry.dots, &self.context, BTreeMap::new(), &other.context);
+            if let Some(entry) = Entry::from_dots(dots) {
+                self.entries.insert(key, entry);
+            }
+        }
+        self.context.merge_from(other.context);
+    }
+}
+
+impl<K, V> StoredCrdt for OrMap<K, V>
+where
+    K: Ord + Serialize + DeserializeOwned + 'static,
+    V: StoredCrdt + Clone,
+{
+}
+
+impl<K: Ord, V: Crdt + Clone> DeltaCrdt for OrMap<K, V> {}
+
+/// The serialized form of an [`OrMap`], with each key written as `[key,
+/// [[replica, counter, value], ...]]`.
+#[derive(Serialize, Deserialize)]
+struct OrMapRepr<K, R, V> {
+    #[serde(rename = "c")]
+    context: CausalContext,
+    #[serde(rename = "e")]
+    entries: Vec<(K, Vec<DotRepr<R, V>>)>,
+}
+
+type DotRepr<R, V> = (R, u64, V);
+
+impl<K: Serialize, V: Serialize> Serialize for OrMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = OrMapRepr {
+            context: self.context.clone(),
+            entries: (self.entries.iter())
+                .map(|(k, x)| {
+                    let dots: Vec<_> = (x.dots.iter())
+                        .map(|(Dot(r, n), value)| (r, *n, value))
+                        .collect();
+                    (k, dots)
+                })
+                .collect(),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for OrMap<K, V>
+where
+    K: Ord + Deserialize<'de>,
+    V: Crdt + Clone + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OrMapRepr::<K, ReplicaId, V>::deserialize(deserializer)?;
+        let entries = (repr.entries.into_iter())
+            .flat_map(|(k, dots)| {
+                let dots = (dots.into_iter())
+                    .map(|(r, n, value)| (Dot(r, n), value))
+                    .collect();
+                Some((k, Entry::from_dots(dots)?))
+            })
+            .collect();
+        Ok(OrMap {
+            context: repr.context,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::crdt::{crdt::GCounter, testing::check_replica_laws};
+
+    #[test]
+    fn or_map_laws() {
+        let base_replica = ReplicaId::new("base");
+        let mut base: OrMap<u8, GCounter> = OrMap::new();
+        base.update(0, &base_replica, |c| c.increment(&base_replica, 1));
+        base.update(1, &base_replica, |c| c.increment(&base_replica, 1));
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..4) {
+                let key = rng.random_range(0..4);
+                if rng.random_bool(0.6) {
+                    x.update(key, replica, |c| c.increment(replica, 1));
+                } else {
+                    x.remove(&key);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,136 @@
+//! Types with useful [`Crdt`] implementations.
+
+use std::{cmp::Ordering, fmt};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::crdt::{Crdt, DeltaCrdt, StoredCrdt};
+
+mod clock;
+mod counter;
+mod document;
+mod dot;
+mod map;
+mod register;
+mod sequence;
+mod set;
+
+pub use clock::{HybridClock, Timestamp};
+pub use counter::{GCounter, PNCounter};
+pub use document::{Document, DocumentError};
+pub use dot::{CausalContext, Dot, VersionVector};
+pub use map::OrMap;
+pub use register::{LwwRegister, MvRegister};
+pub use sequence::{Sequence, Text};
+pub use set::OrSet;
+
+/// Identifies a writer of a CRDT that tracks who made each change, such as a
+/// counter. Every process that writes such a value must use its own replica
+/// ID, e.g. derived from its hostname or chosen randomly at startup.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReplicaId(pub String);
+
+impl ReplicaId {
+    pub fn new(id: impl Into<String>) -> ReplicaId {
+        ReplicaId(id.into())
+    }
+}
+
+impl fmt::Display for ReplicaId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Merge by picking the larger of two values.
+///
+/// ```
+/// use amimono_haze::crdt::{crdt::Max, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| Max(rng.random_range(0..10u8)));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Max<T>(pub T);
+
+impl<T: Ord> Crdt for Max<T> {
+    fn merge_from(&mut self, other: Self) {
+        if let Ordering::Less = self.0.cmp(&other.0) {
+            *self = other
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Max(std::cmp::max(self.0, other.0))
+    }
+}
+
+impl<T: Ord + Serialize + DeserializeOwned + 'static> StoredCrdt for Max<T> {}
+
+impl<T: Ord> DeltaCrdt for Max<T> {}
+
+/// Merge by picking the smaller of two values.
+///
+/// ```
+/// use amimono_haze::crdt::{crdt::Min, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| Min(rng.random_range(0..10u8)));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Min<T>(pub T);
+
+impl<T: Ord> Crdt for Min<T> {
+    fn merge_from(&mut self, other: Self) {
+        if let Ordering::Greater = self.0.cmp(&other.0) {
+            *self = other
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Min(std::cmp::min(self.0, other.0))
+    }
+}
+
+impl<T: Ord + Serialize + DeserializeOwned + 'static> StoredCrdt for Min<T> {}
+
+impl<T: Ord> DeltaCrdt for Min<T> {}
+
+/// Merge by picking the value with a larger version, or merging if they have
+/// the same version.
+///
+/// ```
+/// use std::collections::HashSet;
+///
+/// use amimono_haze::crdt::{crdt::Version, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| {
+///     let len = rng.random_range(0..3);
+///     let set = (0..len).map(|_| rng.random_range(0..4u8)).collect::<HashSet<_>>();
+///     Version(rng.random_range(0..3u8), set)
+/// });
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version<V, T>(pub V, pub T);
+
+impl<V: Ord, T: Crdt> Crdt for Version<V, T> {
+    fn merge_from(&mut self, other: Self) {
+        match self.0.cmp(&other.0) {
+            Ordering::Less => *self = other,
+            Ordering::Equal => self.1.merge_from(other.1),
+            Ordering::Greater => (),
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        match self.0.cmp(&other.0) {
+            Ordering::Less => other,
+            Ordering::Equal => Version(self.0, self.1.merge(other.1)),
+            Ordering::Greater => self,
+        }
+    }
+}
+
+impl<V: Ord + Serialize + DeserializeOwned + 'static, T: StoredCrdt> StoredCrdt for Version<V, T> {}
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+
+use crate::crdt::{
+    Crdt, DeltaCrdt, StoredCrdt,
+    crdt::{
+        HybridClock, ReplicaId, Timestamp,
+        dot::{CausalContext, Dot, merge_dots},
+    },
+};
+
+/// A register where the most recent write wins.
+///
+/// Writes are timestamped by a [`HybridClock`], so a write made after another
+/// one was observed always wins over it, regardless of how the replicas'
+/// system clocks compare. Concurrent writes are ordered by their timestamps,
+/// and the others are lost.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{HybridClock, LwwRegister, ReplicaId}};
+///
+/// let a = HybridClock::new(ReplicaId::new("a"));
+/// let b = HybridClock::new(ReplicaId::new("b"));
+///
+/// let x = LwwRegister::new("x", &a);
+/// let mut y = x.clone();
+/// y.set("y", &b);
+///
+/// // y was written after x was observed
+/// assert_eq!(*x.clone().merge(y.clone()).get(), "y");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: T,
+    timestamp: Timestamp,
+}
+
+impl<T> LwwRegister<T> {
+    /// A register holding a value written now.
+    pub fn new(value: T, clock: &HybridClock) -> LwwRegister<T> {
+        LwwRegister {
+            value,
+            timestamp: clock.now(),
+        }
+    }
+
+    /// Write a new value, which wins over the current one.
+    pub fn set(&mut self, value: T, clock: &HybridClock) {
+        clock.observe(&self.timestamp);
+        self.value = value;
+        self.timestamp = clock.now();
+    }
+
+    pub fn get(&self) -> &T {
+        &self.value
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// When the current value was written.
+    pub fn timestamp(&self) -> &Timestamp {
+        &self.timestamp
+    }
+}
+
+/// Merge by picking the value with the larger timestamp. Timestamps are unique
+/// to a write, so two values with the same timestamp are the same.
+impl<T> Crdt for LwwRegister<T> {
+    fn merge_from(&mut self, other: Self) {
+        if self.timestamp < other.timestamp {
+            *self = other;
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> StoredCrdt for LwwRegister<T> {}
+
+/// A register is small, so it is its own delta.
+impl<T> DeltaCrdt for LwwRegister<T> {}
+
+/// A register that keeps every concurrent write.
+///
+/// Each write is tagged with a [`Dot`], and supersedes the values that the
+/// writer has seen, or a chosen subset of them. Writes that didn't see each
+/// other are kept side by side as siblings until a later write supersedes
+/// them, so that conflicts can be resolved by the application.
+///
+/// Every replica must make its writes to the latest value it has read or
+/// written, as with [`GCounter`][crate::crdt::crdt::GCounter].
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{MvRegister, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = MvRegister::new();
+/// x.set("draft", &a);
+/// let mut y = x.clone();
+///
+/// // concurrent writes are both kept
+/// x.set("left", &a);
+/// y.set("right", &b);
+/// let mut w = x.clone().merge(y.clone());
+/// let mut values: Vec<_> = w.values().copied().collect();
+/// values.sort();
+/// assert_eq!(values, ["left", "right"]);
+///
+/// // a write supersedes the siblings it has seen
+/// w.set("both", &a);
+/// assert_eq!(w.clone().merge(y.clone()).values().collect::<Vec<_>>(), [&"both"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MvRegister<T> {
+    context: CausalContext,
+    values: BTreeMap<Dot, T>,
+}
+
+impl<T> Default for MvRegister<T> {
+    fn default() -> Self {
+        MvRegister {
+            context: CausalContext::new(),
+            values: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> MvRegister<T> {
+    pub fn new() -> MvRegister<T> {
+        MvRegister::default()
+    }
+
+    /// Write a value, superseding every current sibling, and return the delta.
+    pub fn set(&mut self, value: T, replica: &ReplicaId) -> MvRegister<T>
+    where
+        T: Clone,
+    {
+        let supersedes: Vec<Dot> = self.values.keys().cloned().collect();
+        self.set_superseding(value, replica, &supersedes)
+    }
+
+    /// Write a value, superseding only the siblings with the given dots, and
+    /// return the delta. The other siblings are kept alongside the new value.
+    pub fn set_superseding(
+        &mut self,
+        value: T,
+        replica: &ReplicaId,
+        supersedes: &[Dot],
+    ) -> MvRegister<T>
+    where
+        T: Clone,
+    {
+        let superseded: Vec<Dot> = (supersedes.iter())
+            .filter(|dot| self.values.remove(dot).is_some())
+            .cloned()
+            .collect();
+        let dot = self.context.next_dot(replica);
+        self.values.insert(dot.clone(), value.clone());
+        MvRegister {
+            context: superseded.into_iter().chain(Some(dot.clone())).collect(),
+            values: BTreeMap::from([(dot, value)]),
+        }
+    }
+
+    /// Every sibling, along with the dot of the write that made it.
+    pub fn siblings(&self) -> impl Iterator<Item = (&Dot, &T)> {
+        self.values.iter()
+    }
+
+    /// The value of every sibling.
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.values.values()
+    }
+
+    /// The value, if there is exactly one sibling.
+    pub fn get(&self) -> Option<&T> {
+        match self.values.len() {
+            1 => self.values.values().next(),
+            _ => None,
+        }
+    }
+
+    /// Whether there are concurrent writes.
+    pub fn is_conflicted(&self) -> bool {
+        self.values.len() > 1
+    }
+}
+
+impl<T> Crdt for MvRegister<T> {
+    fn merge_from(&mut self, other: Self) {
+        let this = std::mem::take(&mut self.values);
+        self.values = merge_dots(this, &self.context, other.values, &other.context);
+        self.context.merge_from(other.context);
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> StoredCrdt for MvRegister<T> {}
+
+impl<T> DeltaCrdt for MvRegister<T> {}
+
+/// The serialized form of an [`MvRegister`], with each sibling written as
+/// `[replica, counter, value]`.
+#[derive(Serialize, Deserialize)]
+struct MvRegisterRepr<R, T> {
+    #[serde(rename = "c")]
+    context: CausalContext,
+    #[serde(rename = "v")]
+    values: Vec<(R, u64, T)>,
+}
+
+impl<T: Serialize> Serialize for MvRegister<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = MvRegisterRepr {
+            context: self.context.clone(),
+            values: (self.values.iter())
+                .map(|(Dot(r, n), x)| (r, *n, x))
+                .collect(),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for MvRegister<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MvRegisterRepr::<ReplicaId, T>::deserialize(deserializer)?;
+        Ok(MvRegister {
+            context: repr.context,
+            values: (repr.values.into_iter())
+                .map(|(r, n, x)| (Dot(r, n), x))
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::crdt::testing::check_replica_laws;
+
+    #[test]
+    fn lww_register_laws() {
+        let base = LwwRegister::new(0, &HybridClock::new(ReplicaId::new("base")));
+        check_replica_laws(&base, |rng, x, replica| {
+            let clock = HybridClock::new(replica.clone());
+            for _ in 0..rng.random_range(0..3) {
+                x.set(rng.random_range(0..4), &clock);
+            }
+        });
+    }
+
+    #[test]
+    fn mv_register_laws() {
+        let mut base = MvRegister::new();
+        base.set(0, &ReplicaId::new("base"));
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..3) {
+                x.set(rng.random_range(0..4), replica);
+            }
+        });
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, btree_map},
+    fmt,
+    ops::Range,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+
+use crate::crdt::{
+    Crdt, DeltaCrdt, StoredCrdt,
+    crdt::{Dot, ReplicaId},
+};
+
+/// An ordered list that supports inserting and removing elements anywhere,
+/// using the RGA (replicated growable array) algorithm.
+///
+/// Every element is tagged with a [`Dot`], and remembers the element it was
+/// inserted after, its origin. The counter of a new element's dot is larger
+/// than every counter in the sequence, so of the elements inserted after the
+/// same origin, the one inserted last comes first, and an element always
+/// stays next to what was beside it when it was inserted. Concurrent inserts
+/// at the same place are ordered by their dots. Removed elements are kept,
+/// without their values, so that inserts after them can still be placed.
+///
+/// Every replica must make its changes to the latest value it has read or
+/// written, as with [`GCounter`][crate::crdt::crdt::GCounter]. Indexes count
+/// only elements that haven't been removed.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{ReplicaId, Sequence}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = Sequence::new();
+/// x.insert_all(0, ["milk", "eggs", "bread"], &a);
+/// let mut y = x.clone();
+///
+/// // concurrently, a removes the eggs and b adds butter after them
+/// x.remove(1);
+/// y.insert(2, "butter", &b);
+/// let z = x.clone().merge(y.clone());
+/// assert_eq!(z.iter().collect::<Vec<_>>(), [&"milk", &"butter", &"bread"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sequence<T> {
+    elements: BTreeMap<Dot, Element<T>>,
+    /// The dots of the elements in order, including removed ones, and
+    /// whether each is still present. This is computed from the elements. An
+    /// element whose origin is missing, which can happen after merging a
+    /// delta, is left out until the origin arrives.
+    order: Vec<(Dot, bool)>,
+    /// The largest counter of any element.
+    counter: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Element<T> {
+    origin: Option<Dot>,
+    /// The value, or `None` if the element was removed.
+    value: Option<T>,
+}
+
+impl<T> Default for Sequence<T> {
+    fn default() -> Self {
+        Sequence {
+            elements: BTreeMap::new(),
+            order: Vec::new(),
+            counter: 0,
+        }
+    }
+}
+
+impl<T> Sequence<T> {
+    pub fn new() -> Sequence<T> {
+        Sequence::default()
+    }
+
+    /// Insert a value at an index, and return the delta.
+    ///
+    /// Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, value: T, replica: &ReplicaId) -> Sequence<T>
+    where
+        T: Clone,
+    {
+        self.insert_all(index, [value], replica)
+    }
+
+    /// Insert several values at an index, in order, and return the delta.
+    ///
+    /// Panics if the index is greater than the length.
+    pub fn insert_all<I>(&mut self, index: usize, values: I, replica: &ReplicaId) -> Sequence<T>
+    where
+        T: Clone,
+        I: IntoIterator<Item = T>,
+    {
+        let (mut origin, mut at) = match index {
+            0 => (None, 0),
+            _ => {
+                let at = self.position(index - 1).expect("index out of bounds");
+                (Some(self.order[at].0.clone()), at + 1)
+            }
+        };
+        let mut delta = Sequence::new();
+        for value in values {
+            self.counter += 1;
+            let dot = Dot(replica.clone(), self.counter);
+            let element = Element {
+                origin: origin.replace(dot.clone()),
+                value: Some(value),
+            };
+            delta.elements.insert(dot.clone(), element.clone());
+            self.elements.insert(dot.clone(), element);
+            self.order.insert(at, (dot, true));
+            at += 1;
+        }
+        delta.rebuild();
+        delta
+    }
+
+    /// Remove the value at an index, and return the delta.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn remove(&mut self, index: usize) -> Sequence<T> {
+        self.remove_range(index..index + 1)
+    }
+
+    /// Remove the values in a range of indexes, and return the delta.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn remove_range(&mut self, range: Range<usize>) -> Sequence<T> {
+        if range.is_empty() {
+            return Sequence::new();
+        }
+        let start = self.position(range.start).expect("index out of bounds");
+        let mut delta = Sequence::new();
+        let mut remaining = range.len();
+        for (dot, present) in self.order[start..].iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            if std::mem::take(present) {
+                let element = self.elements.get_mut(dot).unwrap();
+                element.value = None;
+                let removed = Element {
+                    origin: element.origin.clone(),
+                    value: None,
+                };
+                delta.elements.insert(dot.clone(), removed);
+                remaining -= 1;
+            }
+        }
+        assert!(remaining == 0, "index out of bounds");
+        delta.rebuild();
+        delta
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.iter().nth(index)
+    }
+
+    /// The values in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (self.order.iter())
+            .filter(|(_, present)| *present)
+            .flat_map(|(dot, _)| self.elements[dot].value.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.iter().filter(|(_, present)| *present).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Change the value at an index in place, and return the delta, which
+    /// holds the whole new value. The sequence must be merged with
+    /// [`merge_with`][Self::merge_with], using a merge of the values.
+    ///
+    /// Panics if the index is out of bounds.
+    pub(crate) fn update_with<F, R>(&mut self, index: usize, update: F) -> Sequence<T>
+    where
+        T: Clone,
+        F: FnOnce(&mut T) -> R,
+    {
+        let at = self.position(index).expect("index out of bounds");
+        let dot = &self.order[at].0;
+        let element = self.elements.get_mut(dot).unwrap();
+        update(element.value.as_mut().unwrap());
+        let mut delta = Sequence::new();
+        delta.elements.insert(dot.clone(), element.clone());
+        delta.rebuild();
+        delta
+    }
+
+    /// Merge, using a merge of the values for elements present on both
+    /// sides. Values are never changed in place by the public methods, so
+    /// [`merge_from`][Crdt::merge_from] keeps this side's value.
+    pub(crate) fn merge_with(&mut self, other: Self, merge: impl Fn(&mut T, T)) {
+        for (dot, that) in other.elements {
+            match self.elements.entry(dot) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(that);
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    let this = entry.get_mut();
+                    match (this.value.as_mut(), that.value) {
+                        (Some(x), Some(y)) => merge(x, y),
+                        (_, None) => this.value = None,
+                        (None, Some(_)) => (),
+                    }
+                }
+            }
+        }
+        self.rebuild();
+    }
+
+    /// The position in the order of the element at an index.
+    fn position(&self, index: usize) -> Option<usize> {
+        (self.order.iter().enumerate())
+            .filter(|(_, (_, present))| *present)
+            .nth(index)
+            .map(|(at, _)| at)
+    }
+
+    /// Recompute the order from the elements. Each element comes right
+    /// before the elements inserted after it, which are ordered by their
+    /// dots, latest first.
+    fn rebuild(&mut self) {
+        let mut children: HashMap<Option<&Dot>, Vec<&Dot>> = HashMap::new();
+        for (dot, element) in self.elements.iter() {
+            children
+                .entry(element.origin.as_ref())
+                .or_default()
+                .push(dot);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| (b.1, &b.0).cmp(&(a.1, &a.0)));
+        }
+        let mut order = Vec::with_capacity(self.elements.len());
+        let mut stack: Vec<&Dot> = Vec::new();
+        stack.extend(children.get(&None).into_iter().flatten().rev());
+        while let Some(dot) = stack.pop() {
+            order.push((dot.clone(), self.elements[dot].value.is_some()));
+            stack.extend(children.get(&Some(dot)).into_iter().flatten().rev());
+        }
+        self.order = order;
+        self.counter = self.elements.keys().map(|x| x.1).max().unwrap_or(0);
+    }
+}
+
+/// Merge by taking every element from both sides. An element removed on
+/// either side is removed.
+impl<T> Crdt for Sequence<T> {
+    fn merge_from(&mut self, other: Self) {
+        self.merge_with(other, |_, _| ());
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + 'static> StoredCrdt for Sequence<T> {}
+
+impl<T> DeltaCrdt for Sequence<T> {}
+
+/// The serialized form of a [`Sequence`] is a list of runs of elements
+/// inserted one after another by the same replica, such as typed text. A run
+/// is written as `[replica, counter, origin, segments]`, where the origin of
+/// the first element is `[replica, counter]` or `null`, and each segment is
+/// either a list of values or the number of removed elements.
+type RunRepr<S> = (ReplicaId, u64, Option<Dot>, Vec<SegmentRepr<S>>);
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SegmentRepr<S> {
+    Live(S),
+    Removed(u64),
+}
+
+impl<T> Sequence<T> {
+    fn to_runs<'a, S>(&'a self, pack: impl Fn(Vec<&'a T>) -> S) -> Vec<RunRepr<S>> {
+        let mut orphans = Vec::new();
+        if self.order.len() < self.elements.len() {
+            let placed: HashSet<&Dot> = self.order.iter().map(|(x, _)| x).collect();
+            orphans.extend(self.elements.keys().filter(|x| !placed.contains(x)));
+        }
+
+        let mut runs: Vec<RunRepr<Vec<&T>>> = Vec::new();
+        let mut prev: Option<&Dot> = None;
+        for dot in self.order.iter().map(|(x, _)| x).chain(orphans) {
+            let element = &self.elements[dot];
+            let continues = prev.is_some_and(|prev| {
+                dot.0 == prev.0 && dot.1 == prev.1 + 1 && element.origin.as_ref() == Some(prev)
+            });
+            if !continues {
+                runs.push((dot.0.clone(), dot.1, element.origin.clone(), Vec::new()));
+            }
+            let segments = &mut runs.last_mut().unwrap().3;
+            match (&element.value, segments.last_mut()) {
+                (Some(x), Some(SegmentRepr::Live(xs))) => xs.push(x),
+                (Some(x), _) => segments.push(SegmentRepr::Live(vec![x])),
+                (None, Some(SegmentRepr::Removed(n))) => *n += 1,
+                (None, _) => segments.push(SegmentRepr::Removed(1)),
+            }
+            prev = Some(dot);
+        }
+
+        (runs.into_iter())
+            .map(|(replica, counter, origin, segments)| {
+                let segments = (segments.into_iter())
+                    .map(|x| match x {
+                        SegmentRepr::Live(xs) => SegmentRepr::Live(pack(xs)),
+                        SegmentRepr::Removed(n) => SegmentRepr::Removed(n),
+                    })
+                    .collect();
+                (replica, counter, origin, segments)
+            })
+            .collect()
+    }
+
+    fn from_runs<S>(runs: Vec<RunRepr<S>>, unpack: impl Fn(S) -> Vec<T>) -> Sequence<T> {
+        let mut res = Sequence::new();
+        for (replica, mut counter, mut origin, segments) in runs {
+            for segment in segments {
+                let values: Vec<Option<T>> = match segment {
+                    SegmentRepr::Live(xs) => unpack(xs).into_iter().map(Some).collect(),
+                    SegmentRepr::Removed(n) => (0..n).map(|_| None).collect(),
+                };
+                for value in values {
+                    let dot = Dot(replica.clone(), counter);
+                    let element = Element {
+                        origin: origin.replace(dot.clone()),
+                        value,
+                    };
+                    res.elements.insert(dot, element);
+                    counter += 1;
+                }
+            }
+        }
+        res.rebuild();
+        res
+    }
+}
+
+impl<T: Serialize> Serialize for Sequence<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_runs(|xs| xs).serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Sequence<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let runs = Vec::<RunRepr<Vec<T>>>::deserialize(deserializer)?;
+        Ok(Sequence::from_runs(runs, |xs| xs))
+    }
+}
+
+/// A [`Sequence`] of characters, for collaboratively edited text.
+///
+/// Indexes are in characters, not bytes. Runs of characters are serialized
+/// as strings.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{ReplicaId, Text}};
+///
+/// let (phone, laptop) = (ReplicaId::new("phone"), ReplicaId::new("laptop"));
+///
+/// let mut x = Text::new();
+/// x.insert(0, "buy milk", &laptop);
+/// let mut y = x.clone();
+///
+/// // edited offline on both devices
+/// x.insert(8, " and eggs", &laptop);
+/// y.remove(0..4);
+/// y.insert(0, "get ", &phone);
+/// assert_eq!(x.clone().merge(y.clone()).to_string(), "get milk and eggs");
+///
+/// // typed text is stored compactly
+/// assert_eq!(
+///     serde_json::to_string(&x).unwrap(),
+///     r#"[["laptop",1,null,["buy milk and eggs"]]]"#,
+/// );
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Text(Sequence<char>);
+
+impl Text {
+    pub fn new() -> Text {
+        Text::default()
+    }
+
+    /// Insert a string at a character index, and return the delta.
+    ///
+    /// Panics if the index is greater than the length.
+    pub fn insert(&mut self, index: usize, text: &str, replica: &ReplicaId) -> Text {
+        Text(self.0.insert_all(index, text.chars(), replica))
+    }
+
+    /// Remove a range of characters, and return the delta.
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn remove(&mut self, range: Range<usize>) -> Text {
+        Text(self.0.remove_range(range))
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> {
+        self.0.iter().copied()
+    }
+
+    /// The length in characters.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The underlying sequence of characters.
+    pub fn as_sequence(&self) -> &Sequence<char> {
+        &self.0
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.chars().try_for_each(|c| fmt::Write::write_char(f, c))
+    }
+}
+
+impl Crdt for Text {
+    fn merge_from(&mut self, other: Self) {
+        self.0.merge_from(other.0);
+    }
+}
+
+impl StoredCrdt for Text {}
+
+impl DeltaCrdt for Text {}
+
+impl Serialize for Text {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.0.to_runs(|xs| xs.into_iter().collect::<String>())).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Text {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let runs = Vec::<RunRepr<String>>::deserialize(deserializer)?;
+        Ok(Text(Sequence::from_runs(runs, |xs| xs.chars().collect())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::crdt::testing::check_replica_laws;
+
+    #[test]
+    fn sequence_laws() {
+        let mut base = Sequence::new();
+        base.insert_all(0, 0..4, &ReplicaId::new("base"));
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..4) {
+                if !x.is_empty() && rng.random_bool(0.3) {
+                    x.remove(rng.random_range(0..x.len()));
+                } else {
+                    let value = rng.random_range(0..100);
+                    x.insert(rng.random_range(0..=x.len()), value, replica);
+                }
+            }
+        });
+    }
+}
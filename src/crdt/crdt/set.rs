@@ -0,0 +1,226 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::DeserializeOwned};
+
+use crate::crdt::{
+    Crdt, DeltaCrdt, StoredCrdt,
+    crdt::{
+        ReplicaId,
+        dot::{CausalContext, Dot, merge_dots},
+    },
+};
+
+/// A set that supports removing elements.
+///
+/// Every add and remove is tagged with a [`Dot`]. An add or remove of an
+/// element supersedes every earlier add and remove of it that the writer has
+/// seen, so an element can be re-added after it was removed. An element is in
+/// the set if it has been added, and no remove of it is concurrent with or
+/// newer than the add, i.e. a remove wins over a concurrent add.
+///
+/// Every replica must make its changes to the latest value it has read or
+/// written, as with [`GCounter`][crate::crdt::crdt::GCounter]. A removed
+/// element takes up space until it is added again, so that it can win over
+/// adds it hasn't seen.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{OrSet, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut x = OrSet::new();
+/// x.insert("apple", &a);
+/// x.insert("pear", &a);
+/// let mut y = x.clone();
+///
+/// // concurrently, a removes the apple, and b adds it again
+/// x.remove(&"apple", &a);
+/// y.insert("apple", &b);
+/// assert!(!x.clone().merge(y.clone()).contains(&"apple"));
+///
+/// // an add after seeing the remove wins
+/// let mut w = x.clone().merge(y.clone());
+/// w.insert("apple", &b);
+/// assert!(w.clone().merge(x.clone()).contains(&"apple"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrSet<T> {
+    context: CausalContext,
+    /// The dots of the adds (`true`) and removes (`false`) of each element that
+    /// haven't been superseded.
+    entries: BTreeMap<T, BTreeMap<Dot, bool>>,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        OrSet {
+            context: CausalContext::new(),
+            entries: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Ord> OrSet<T> {
+    pub fn new() -> OrSet<T> {
+        OrSet::default()
+    }
+
+    /// Add an element, and return the delta.
+    pub fn insert(&mut self, value: T, replica: &ReplicaId) -> OrSet<T>
+    where
+        T: Clone,
+    {
+        self.change(value, replica, true)
+    }
+
+    /// Remove an element, and return the delta. This wins over adds of the
+    /// element made without seeing the remove, even if the element isn't in
+    /// this copy of the set.
+    pub fn remove(&mut self, value: &T, replica: &ReplicaId) -> OrSet<T>
+    where
+        T: Clone,
+    {
+        self.change(value.clone(), replica, false)
+    }
+
+    /// Add or remove an element, superseding its current dots. The delta has
+    /// the new dot, and the superseded dots in its context.
+    fn change(&mut self, value: T, replica: &ReplicaId, add: bool) -> OrSet<T>
+    where
+        T: Clone,
+    {
+        let dot = self.context.next_dot(replica);
+        let dots = BTreeMap::from([(dot.clone(), add)]);
+        let old = self.entries.insert(value.clone(), dots.clone());
+        let superseded = old.into_iter().flat_map(|x| x.into_keys());
+        OrSet {
+            context: superseded.chain(Some(dot)).collect(),
+            entries: BTreeMap::from([(value, dots)]),
+        }
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.entries.get(value).is_some_and(is_present)
+    }
+
+    /// The elements in the set, in order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        (self.entries.iter())
+            .filter(|(_, dots)| is_present(dots))
+            .map(|(value, _)| value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+fn is_present(dots: &BTreeMap<Dot, bool>) -> bool {
+    !dots.is_empty() && dots.values().all(|add| *add)
+}
+
+impl<T: Ord> Crdt for OrSet<T> {
+    fn merge_from(&mut self, other: Self) {
+        let mut this = std::mem::take(&mut self.entries);
+        for (value, that) in other.entries {
+            let dots = this.remove(&value).unwrap_or_default();
+            let dots = merge_dots(dots, &self.context, that, &other.context);
+            if !dots.is_empty() {
+                self.entries.insert(value, dots);
+            }
+        }
+        for (value, dots) in this {
+            let dots = merge_dots(dots, &self.context, BTreeMap::new(), &other.context);
+            if !dots.is_empty() {
+                self.entries.insert(value, dots);
+            }
+        }
+        self.context.merge_from(other.context);
+    }
+}
+
+impl<T: Ord + Serialize + DeserializeOwned + 'static> StoredCrdt for OrSet<T> {}
+
+impl<T: Ord> DeltaCrdt for OrSet<T> {}
+
+/// The serialized form of an [`OrSet`]. Dots are written as `[replica,
+/// counter]` pairs, grouped by element.
+#[derive(Serialize, Deserialize)]
+struct OrSetRepr<T, R> {
+    #[serde(rename = "c")]
+    context: CausalContext,
+    /// Elements with the dots of their adds.
+    #[serde(rename = "a")]
+    added: Vec<(T, Vec<(R, u64)>)>,
+    /// Elements with the dots of their removes.
+    #[serde(rename = "r")]
+    removed: Vec<(T, Vec<(R, u64)>)>,
+}
+
+impl<T: Serialize> Serialize for OrSet<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let group = |add: bool| {
+            (self.entries.iter())
+                .map(|(value, dots)| {
+                    let dots: Vec<(&ReplicaId, u64)> = (dots.iter())
+                        .filter(|(_, x)| **x == add)
+                        .map(|(Dot(r, n), _)| (r, *n))
+                        .collect();
+                    (value, dots)
+                })
+                .filter(|(_, dots)| !dots.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let repr = OrSetRepr {
+            context: self.context.clone(),
+            added: group(true),
+            removed: group(false),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de, T: Ord + Deserialize<'de>> Deserialize<'de> for OrSet<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OrSetRepr::<T, ReplicaId>::deserialize(deserializer)?;
+        let mut entries: BTreeMap<T, BTreeMap<Dot, bool>> = BTreeMap::new();
+        for (dots, add) in [(repr.added, true), (repr.removed, false)] {
+            for (value, dots) in dots {
+                let entry = entries.entry(value).or_default();
+                entry.extend(dots.into_iter().map(|(r, n)| (Dot(r, n), add)));
+            }
+        }
+        Ok(OrSet {
+            context: repr.context,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+    use crate::crdt::testing::check_replica_laws;
+
+    #[test]
+    fn or_set_laws() {
+        let mut base = OrSet::new();
+        base.insert(0, &ReplicaId::new("base"));
+        base.insert(1, &ReplicaId::new("base"));
+        check_replica_laws(&base, |rng, x, replica| {
+            for _ in 0..rng.random_range(0..4) {
+                if rng.random_bool(0.5) {
+                    x.insert(rng.random_range(0..4), replica);
+                } else {
+                    x.remove(&rng.random_range(0..4), replica);
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,194 @@
+//! Leader election for controller instances.
+//!
+//! Any number of controllers may be running, but only one of them should be
+//! acting on the cluster at a time. Every router keeps a small lease table,
+//! and a controller is the leader while a majority of the routers have
+//! granted it the lease. The majority is counted over the routers it
+//! discovered together with the nodes of the newest ring config they report,
+//! so that a controller that only discovers part of the cluster, e.g. during a
+//! discovery glitch, can't elect itself with a majority of that part. Before
+//! the cluster is bootstrapped, only discovered routers are counted. Routers
+//! time leases with their own clocks, so no
+//! cross-machine clock agreement is needed; the controller conservatively
+//! measures its own lease from the moment it started asking for it.
+//!
+//! An iteration of the controller can take longer than the lease, e.g. when
+//! pushes to nodes time out, so the controller checks that its lease hasn't
+//! run out before every RPC that changes the cluster, rather than only when it
+//! campaigns. A controller that has lost its lease stops acting within one
+//! RPC, though an RPC already sent may still arrive after the lease ends.
+//!
+//! Lease grants are not persisted. A router that restarts forgets the lease
+//! it granted, which in the worst case lets a second controller collect a
+//! majority before the first one's lease runs out, and both act until then.
+
+use std::{
+    collections::BTreeSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use amimono::runtime::{self, Location};
+use futures::future::join_all;
+
+use crate::crdt::{
+    ring::{RingConfig, RingUpdateConfig},
+    router::{CrdtRouterClient, CrdtRouterComponent},
+};
+
+/// How long a granted lease lasts before it must be renewed.
+pub const LEASE_TTL: Duration = Duration::from_secs(10);
+
+/// The router side of the lease. Grants the lease to at most one candidate at
+/// a time.
+pub struct LeaseTable {
+    current: Mutex<Option<Grant>>,
+}
+
+struct Grant {
+    holder: String,
+    expires: Instant,
+}
+
+impl LeaseTable {
+    pub fn new() -> LeaseTable {
+        LeaseTable {
+            current: Mutex::new(None),
+        }
+    }
+
+    /// Grant the lease to the candidate if it is free, expired, or already
+    /// held by the candidate. Returns the holder after the request.
+    pub fn request(&self, candidate: String, ttl: Duration) -> String {
+        let now = Instant::now();
+        let mut current = self.current.lock().expect("failed to get lease lock");
+        let available = match current.as_ref() {
+            None => true,
+            Some(g) => g.holder == candidate || g.expires <= now,
+        };
+        if available {
+            *current = Some(Grant {
+                holder: candidate,
+                expires: now + ttl,
+            });
+        }
+        current.as_ref().unwrap().holder.clone()
+    }
+}
+
+/// The result of a round of campaigning.
+pub enum Campaign {
+    /// We were not the leader before, but are now.
+    Elected,
+    /// We were already the leader and have renewed the lease.
+    Leading,
+    /// Someone else holds the lease, or not enough routers answered.
+    Standby,
+}
+
+/// The controller side of the lease.
+pub struct Election {
+    candidate: String,
+    router: CrdtRouterClient,
+    leader_until: Option<Instant>,
+}
+
+impl Election {
+    pub fn new() -> Election {
+        Election {
+            candidate: format!("{:016x}", rand::random::<u64>()),
+            router: CrdtRouterClient::new(),
+            leader_until: None,
+        }
+    }
+
+    fn is_leader(&self) -> bool {
+        self.leader_until.is_some_and(|t| Instant::now() < t)
+    }
+
+    /// When our lease runs out, measured from the start of the campaign that
+    /// won or renewed it, or `None` if we aren't the leader.
+    pub fn leader_until(&self) -> Option<Instant> {
+        self.leader_until
+    }
+
+    /// Ask every discovered router for the lease.
+    pub async fn campaign(&mut self) -> Campaign {
+        let was_leader = self.is_leader();
+        let start = Instant::now();
+
+        let routers = match runtime::discover::<CrdtRouterComponent>().await {
+            Ok(x) => x,
+            Err(e) => {
+                log::warn!("election: failed to discover routers: {e}");
+                return self.lose(was_leader);
+            }
+        };
+        let routers: Vec<Location> = routers
+            .into_iter()
+            .filter(|x| matches!(x, Location::Stable(_)))
+            .collect();
+        if routers.is_empty() {
+            return self.lose(was_leader);
+        }
+
+        let ttl_ms = LEASE_TTL.as_millis() as u64;
+        let answers = join_all(routers.iter().map(|loc| {
+            let router = self.router.at(loc.clone());
+            let candidate = self.candidate.clone();
+            async move {
+                let ring = router.get_ring().await.ok().flatten();
+                (router.lease(candidate, ttl_ms).await, ring)
+            }
+        }))
+        .await;
+
+        let mut voters: BTreeSet<String> = (routers.iter())
+            .filter_map(|x| match x {
+                Location::Stable(s) => Some(s.clone()),
+                _ => None,
+            })
+            .collect();
+        let newest = (answers.iter())
+            .filter_map(|(_, ring)| ring.as_ref())
+            .max_by_key(|cf| cf.epoch);
+        voters.extend(newest.into_iter().flat_map(ring_members));
+        let votes = answers
+            .iter()
+            .filter(|(x, _)| matches!(x, Ok(h) if *h == self.candidate))
+            .count();
+
+        if votes * 2 > voters.len() {
+            self.leader_until = Some(start + LEASE_TTL);
+            if was_leader {
+                Campaign::Leading
+            } else {
+                log::info!(
+                    "controller {} elected leader ({votes}/{} routers)",
+                    self.candidate,
+                    voters.len()
+                );
+                Campaign::Elected
+            }
+        } else {
+            self.lose(was_leader)
+        }
+    }
+
+    fn lose(&mut self, was_leader: bool) -> Campaign {
+        if was_leader {
+            log::warn!("controller {} lost leadership", self.candidate);
+        }
+        self.leader_until = None;
+        Campaign::Standby
+    }
+}
+
+/// The network IDs of the nodes in a ring config, including one being added.
+fn ring_members(cf: &RingConfig) -> impl Iterator<Item = String> + '_ {
+    let adding = match &cf.update {
+        Some(RingUpdateConfig::ToAdd { ni, .. }) => Some(ni),
+        _ => None,
+    };
+    (cf.nodes.values()).chain(adding).map(|ni| ni.0.clone())
+}
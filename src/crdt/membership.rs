@@ -0,0 +1,91 @@
+//! Where the controller gets the desired cluster membership from.
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::{
+    Crdt, CrdtClient, StoredCrdt,
+    ring::{Labels, NetworkId},
+};
+
+/// The source of the desired cluster membership.
+#[derive(Debug, Clone, Default)]
+pub enum Membership {
+    /// Every discovered router is a member, with the default weight. Ring
+    /// changes follow processes coming and going.
+    #[default]
+    Discovery,
+
+    /// Members and their weights are read from a JSON-encoded
+    /// [`MembershipManifest`] file on the controller's machine.
+    File(PathBuf),
+
+    /// Members and their weights are read from a [`MembershipManifest`]
+    /// stored in the cluster itself. The scope is bound when the settings are
+    /// installed. Until the manifest has been written, which is only possible
+    /// once the ring exists, the cluster is bootstrapped from discovery.
+    Stored { scope: String, key: String },
+}
+
+/// An operator-managed list of cluster members.
+///
+/// With a manifest, discovery is only used to check on node health, and the
+/// ring only changes when the manifest does.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MembershipManifest {
+    /// Should be incremented on every change, so that a stored manifest
+    /// resolves to the most recent one.
+    pub version: u64,
+
+    /// The weight of each member, i.e. how many virtual nodes it gets. A
+    /// member with a weight of zero gets configs but owns no part of the ring.
+    pub nodes: BTreeMap<NetworkId, usize>,
+
+    /// Labels for members, such as their zone or rack. These take precedence
+    /// over [`ControllerSettings::labels`][crate::crdt::ControllerSettings::labels].
+    #[serde(default)]
+    pub labels: BTreeMap<NetworkId, Labels>,
+}
+
+/// Merge by picking the manifest with the larger version. Two different
+/// manifests with the same version can only come from racing operators, in
+/// which case the larger one is picked so that every replica agrees.
+impl Crdt for MembershipManifest {
+    fn merge_from(&mut self, other: Self) {
+        if (self.version, &self.nodes, &self.labels) < (other.version, &other.nodes, &other.labels)
+        {
+            *self = other;
+        }
+    }
+}
+
+impl StoredCrdt for MembershipManifest {}
+
+impl Membership {
+    pub(crate) fn bind(&self) {
+        if let Membership::Stored { scope, .. } = self {
+            MembershipManifest::bind(scope);
+        }
+    }
+
+    /// Read the manifest. Returns `None` when membership comes from discovery,
+    /// or when a stored manifest has not been written yet.
+    pub(crate) async fn load(&self) -> Result<Option<MembershipManifest>, String> {
+        match self {
+            Membership::Discovery => Ok(None),
+            Membership::File(path) => {
+                let data = tokio::fs::read(path)
+                    .await
+                    .map_err(|e| format!("failed to read manifest {path:?}: {e}"))?;
+                let manifest = serde_json::from_slice(&data)
+                    .map_err(|e| format!("failed to parse manifest {path:?}: {e}"))?;
+                Ok(Some(manifest))
+            }
+            Membership::Stored { scope, key } => CrdtClient::<MembershipManifest>::new(scope)
+                .get(key)
+                .await
+                .map_err(|e| format!("failed to read manifest {scope}/{key}: {e:?}")),
+        }
+    }
+}
@@ -12,14 +12,82 @@ use amimono::config::{AppBuilder, JobBuilder};
 
 pub mod crdt;
 
+pub(crate) mod admin;
+pub mod balance;
 pub(crate) mod client;
 pub(crate) mod controller;
-pub(crate) mod ring;
+pub(crate) mod lease;
+pub(crate) mod membership;
+pub mod partition;
+pub mod ring;
 pub(crate) mod router;
+pub(crate) mod settings;
 pub(crate) mod storage;
+pub mod testing;
 
+/// ```
+/// use amimono_haze::crdt::{Crdt, crdt::{GCounter, Max, ReplicaId}};
+///
+/// #[derive(Clone, Debug, Default, Crdt)]
+/// struct Profile {
+///     visits: GCounter,
+///     #[crdt(merge_with = "merge_earliest")]
+///     created_ms: u64,
+///     #[crdt(skip)]
+///     cached_summary: Option<String>,
+/// }
+///
+/// fn merge_earliest(this: &mut u64, other: u64) {
+///     *this = std::cmp::min(*this, other);
+/// }
+///
+/// // type parameters that are merged must be `Crdt`
+/// #[derive(Clone, Debug, PartialEq, Crdt)]
+/// struct Tagged<T> {
+///     value: T,
+///     tag: Max<u32>,
+/// }
+///
+/// let a = ReplicaId::new("a");
+/// let mut x = Profile { created_ms: 20, ..Profile::default() };
+/// x.visits.increment(&a, 1);
+/// let y = Profile { created_ms: 10, cached_summary: Some("old".to_owned()), ..Profile::default() };
+/// let z = x.merge(y);
+/// assert_eq!((z.visits.value(), z.created_ms, z.cached_summary), (1, 10, None));
+///
+/// let t = Tagged { value: Max(1), tag: Max(7) }.merge(Tagged { value: Max(3), tag: Max(2) });
+/// assert_eq!(t, Tagged { value: Max(3), tag: Max(7) });
+/// ```
+///
+/// Only structs can derive [`Crdt`][macro@Crdt]:
+///
+/// ```compile_fail
+/// use amimono_haze::crdt::Crdt;
+///
+/// #[derive(Crdt)]
+/// enum Choice {
+///     Left,
+///     Right,
+/// }
+/// ```
+///
+/// ```compile_fail
+/// use amimono_haze::crdt::Crdt;
+///
+/// #[derive(Crdt)]
+/// union Bits {
+///     int: u32,
+///     float: f32,
+/// }
+/// ```
+pub use amimono_haze_derive::{Crdt, StoredCrdt};
+
+pub use admin::{ControllerControls, ControllerPlan, CrdtAdmin, ForcedBootstrap, KeyPlacement};
 pub use client::CrdtClient;
+pub use membership::{Membership, MembershipManifest};
+pub use router::CompositeKey;
 use serde::{Serialize, de::DeserializeOwned};
+pub use settings::ControllerSettings;
 
 /// The main CRDT trait.
 ///
@@ -28,7 +96,7 @@ pub trait Crdt: Sized {
     /// Take the other value and merge it into this one. In order to maintain
     /// the CRDT invariants, this operation must be commutative, associative,
     /// and idempotent. See the [module-level documentation][crate::crdt] for
-    /// more details.
+    /// more details, and [`testing`] for checking an implementation.
     fn merge_from(&mut self, other: Self);
 
     /// Merge the two values into a new one. This operation must have the same
@@ -49,7 +117,101 @@ pub trait StoredCrdt: Crdt + Serialize + DeserializeOwned + 'static {
     /// This is a provided method that must be called before application startup
     /// for all `StoredCrdt` instances that will be used.
     fn bind(scope: &str) {
-        bind_scope::<Self>(scope);
+        bind_scope::<Self>(scope, ScopeOptions::default());
+    }
+
+    /// Bind this type to a scope, with options.
+    ///
+    /// Like [`bind`][Self::bind], this must be called before application
+    /// startup, and must be called the same way by every process.
+    fn bind_with(scope: &str, options: ScopeOptions) {
+        bind_scope::<Self>(scope, options);
+    }
+}
+
+/// A trait for CRDTs whose changes can be sent on their own, as deltas.
+///
+/// A delta is a value of the same type that holds only what a change did, and
+/// is returned by the type's mutators, e.g.
+/// [`OrSet::insert`][crdt::OrSet::insert]. Merging a delta into any value
+/// that has seen what the change was made to has the same effect as merging
+/// in the whole changed value. Deltas can be merged in any order and more than
+/// once, and merging several deltas together gives a delta with all of their
+/// changes. [`CrdtClient::put_delta`] sends a delta to be merged into a stored
+/// value.
+///
+/// Deltas can also be taken between two versions of a value with
+/// [`delta_since`][Self::delta_since], e.g. to send the changes made to a value
+/// read earlier.
+///
+/// ```
+/// use amimono_haze::crdt::{Crdt, DeltaCrdt, crdt::{OrSet, ReplicaId}};
+///
+/// let (a, b) = (ReplicaId::new("a"), ReplicaId::new("b"));
+///
+/// let mut stored = OrSet::new();
+/// stored.insert("apple", &a);
+/// let mut x = stored.clone();
+///
+/// let mut deltas = x.insert("pear", &b);
+/// deltas.merge_from(x.remove(&"apple", &b));
+/// stored.apply_delta(deltas);
+/// assert_eq!(stored, x);
+///
+/// // or take the changes since an earlier version
+/// let old = x.clone();
+/// x.insert("plum", &a);
+/// stored.apply_delta(x.delta_since(&old));
+/// assert_eq!(stored, x);
+/// ```
+pub trait DeltaCrdt: Crdt {
+    /// A delta with every change in this value that `old` doesn't have.
+    ///
+    /// The default implementation returns the whole value, which is always a
+    /// valid delta, if not a small one.
+    fn delta_since(&self, old: &Self) -> Self
+    where
+        Self: Clone,
+    {
+        let _ = old;
+        self.clone()
+    }
+
+    /// Merge a delta into this value.
+    ///
+    /// Storage merges deltas with [`merge_from`][Crdt::merge_from], so an
+    /// implementation must have the same effect. The default implementation
+    /// uses it.
+    fn apply_delta(&mut self, delta: Self) {
+        self.merge_from(delta);
+    }
+}
+
+/// Options for a scope, set when a type is bound to it with
+/// [`StoredCrdt::bind_with`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeOptions {
+    /// Place keys by their hash tag, if they have one. The hash tag of a key
+    /// is the part between the first `{` and the next `}`, if that is not
+    /// empty, e.g. `user42` in `{user42}.profile`. Keys with the same hash tag
+    /// are always stored on the same node, even across scopes, which makes
+    /// multi-key operations on them possible. Keys without a hash tag are
+    /// placed as usual.
+    ///
+    /// Changing this for a scope that already has data changes where its keys
+    /// belong, but nothing moves the keys that are already stored, so they
+    /// can no longer be found. It should only be set for new scopes.
+    pub hash_tags: bool,
+}
+
+/// The hash tag of a key. See [`ScopeOptions::hash_tags`].
+pub fn hash_tag(key: &str) -> Option<&str> {
+    let start = key.find('{')? + 1;
+    let len = key[start..].find('}')?;
+    if len == 0 {
+        None
+    } else {
+        Some(&key[start..start + len])
     }
 }
 
@@ -60,9 +222,40 @@ impl Crdt for () {
 
 impl StoredCrdt for () {}
 
+impl DeltaCrdt for () {}
+
+/// Merge two options, treating `None` as the bottom element.
+///
+/// ```
+/// use amimono_haze::crdt::{crdt::Max, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| rng.random_bool(0.7).then(|| Max(rng.random_range(0..4u8))));
+/// ```
+impl<T: Crdt> Crdt for Option<T> {
+    fn merge_from(&mut self, other: Self) {
+        match (self.as_mut(), other) {
+            (_, None) => {}
+            (None, Some(that)) => *self = Some(that),
+            (Some(this), Some(that)) => this.merge_from(that),
+        }
+    }
+}
+
+impl<T: StoredCrdt> StoredCrdt for Option<T> {}
+
+impl<T: DeltaCrdt> DeltaCrdt for Option<T> {}
+
 // TODO: tuple impl macros...
 
 /// Merge two pairs by merging the left and right values.
+///
+/// ```
+/// use amimono_haze::crdt::{crdt::{Max, Min}, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| (Max(rng.random_range(0..4u8)), Min(rng.random_range(0..4u8))));
+/// ```
 impl<T0, T1> Crdt for (T0, T1)
 where
     T0: Crdt,
@@ -87,6 +280,16 @@ where
 /// Merge two vectors by merging values at the same index. The vector will be
 /// extended to the length of the longest input. This is a generalized version
 /// of the behavior for tuples.
+///
+/// ```
+/// use amimono_haze::crdt::{crdt::Max, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| {
+///     let len = rng.random_range(0..4);
+///     (0..len).map(|_| Max(rng.random_range(0..4u8))).collect::<Vec<_>>()
+/// });
+/// ```
 impl<T> Crdt for Vec<T>
 where
     T: Crdt,
@@ -117,6 +320,18 @@ where
 impl<T> StoredCrdt for Vec<T> where T: StoredCrdt {}
 
 /// Merge two sets by computing their union.
+///
+/// ```
+/// use std::collections::HashSet;
+///
+/// use amimono_haze::crdt::testing::check_laws;
+/// use rand::Rng;
+///
+/// check_laws(|rng| {
+///     let len = rng.random_range(0..4);
+///     (0..len).map(|_| rng.random_range(0..6u8)).collect::<HashSet<_>>()
+/// });
+/// ```
 impl<T> Crdt for HashSet<T>
 where
     T: Eq + Hash,
@@ -130,8 +345,34 @@ where
 
 impl<T> StoredCrdt for HashSet<T> where T: Eq + Hash + Serialize + DeserializeOwned + 'static {}
 
+/// A delta is a set of added elements.
+impl<T: Eq + Hash> DeltaCrdt for HashSet<T> {
+    fn delta_since(&self, old: &Self) -> Self
+    where
+        Self: Clone,
+    {
+        let mut res = self.clone();
+        res.retain(|x| !old.contains(x));
+        res
+    }
+}
+
 /// Merge two hash maps by combining their keys and merging values for keys that
 /// appear in both maps.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use amimono_haze::crdt::{crdt::Max, testing::check_laws};
+/// use rand::Rng;
+///
+/// check_laws(|rng| {
+///     let len = rng.random_range(0..4);
+///     (0..len)
+///         .map(|_| (rng.random_range(0..4u8), Max(rng.random_range(0..4u8))))
+///         .collect::<HashMap<_, _>>()
+/// });
+/// ```
 impl<K: Eq + Hash, T: Crdt> Crdt for HashMap<K, T> {
     fn merge_from(&mut self, other: Self) {
         for (key, that) in other.into_iter() {
@@ -151,6 +392,9 @@ where
 {
 }
 
+/// A delta is a map of the changed keys to deltas of their values.
+impl<K: Eq + Hash, T: DeltaCrdt> DeltaCrdt for HashMap<K, T> {}
+
 pub(crate) fn install_controller(job: &mut JobBuilder, prefix: &str) {
     job.add_component(controller::component(prefix));
 }
@@ -185,15 +429,20 @@ impl<T: StoredCrdt> StoredCrdtBinding for StoredCrdtBindingImpl<T> {
     }
 }
 
-static SCOPES: LazyLock<RwLock<HashMap<String, Box<dyn StoredCrdtBinding>>>> =
+struct Scope {
+    binding: Box<dyn StoredCrdtBinding>,
+    options: ScopeOptions,
+}
+
+static SCOPES: LazyLock<RwLock<HashMap<String, Scope>>> =
     LazyLock::new(|| RwLock::new(HashMap::new()));
 
-fn bind_scope<T: StoredCrdt>(scope: &str) {
-    let binding = StoredCrdtBindingImpl::<T>(PhantomData);
+fn bind_scope<T: StoredCrdt>(scope: &str, options: ScopeOptions) {
+    let binding = Box::new(StoredCrdtBindingImpl::<T>(PhantomData));
     SCOPES
         .write()
         .expect("failed to get SCOPES lock")
-        .insert(scope.to_owned(), Box::new(binding));
+        .insert(scope.to_owned(), Scope { binding, options });
 }
 
 pub(crate) fn check_scope<T: StoredCrdt>(scope: &str) -> bool {
@@ -202,6 +451,7 @@ pub(crate) fn check_scope<T: StoredCrdt>(scope: &str) -> bool {
         .expect("failed to get SCOPES lock")
         .get(scope)
         .expect("scope not found")
+        .binding
         .inner();
     ty == TypeId::of::<T>()
 }
@@ -212,5 +462,17 @@ pub(crate) fn merge_in_scope(scope: &str, a: &[u8], b: &[u8]) -> Result<Vec<u8>,
         .expect("failed to get SCOPES lock")
         .get(scope)
         .ok_or("scope not found")?
+        .binding
         .merge(a, b)
 }
+
+/// The hash tag that places a key, if its scope uses hash tags. Unbound scopes
+/// don't.
+pub(crate) fn hash_tag_in_scope<'k>(scope: &str, key: &'k str) -> Option<&'k str> {
+    let hash_tags = SCOPES
+        .read()
+        .expect("failed to get SCOPES lock")
+        .get(scope)
+        .is_some_and(|x| x.options.hash_tags);
+    if hash_tags { hash_tag(key) } else { None }
+}
@@ -0,0 +1,615 @@
+//! Mapping keys to virtual nodes.
+//!
+//! A [`Partitioner`] decides which virtual node owns each key. The consistent
+//! hashing ring ([`HashRing`]) is the default. Rendezvous and jump hashing are
+//! also available, and are implemented over a fixed table of slots so that
+//! ownership can be enumerated as ranges of key hashes like the ring's.
+//!
+//! Whatever the partitioner, a change to the ring config is carried out the
+//! same way: every key whose owner differs between the config without and
+//! with the update is moved by its current owner. See [`Placement`].
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crdt::ring::{
+    HashRing, Labels, NetworkId, RingConfig, RingKey, RingUpdateConfig, VirtualNodeId,
+};
+
+/// The sha256 hash of a key. Keys are placed by their hash.
+pub type KeyHash = [u8; 32];
+
+impl RingKey for KeyHash {
+    fn as_sha256(&self) -> [u8; 32] {
+        *self
+    }
+}
+
+/// The available partitioners.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PartitionerKind {
+    /// Consistent hashing. Adding or removing a virtual node only moves keys
+    /// between it and its neighbor on the ring.
+    #[default]
+    Ring,
+    /// Rendezvous (highest random weight) hashing. Adding or removing a
+    /// virtual node moves keys between it and every other virtual node, in
+    /// roughly equal amounts.
+    Rendezvous,
+    /// Jump consistent hashing, with virtual nodes numbered in sorted order.
+    /// Very even, but any change other than adding or removing the last
+    /// virtual node renumbers the ones after it and moves a lot of data.
+    Jump,
+}
+
+impl PartitionerKind {
+    /// Build a partitioner of this kind over the given virtual nodes.
+    pub fn build(&self, nodes: impl Iterator<Item = VirtualNodeId>) -> Box<dyn Partitioner> {
+        match self {
+            PartitionerKind::Ring => Box::new(HashRing::from_nodes(nodes)),
+            PartitionerKind::Rendezvous => Box::new(SlotTable::rendezvous(nodes)),
+            PartitionerKind::Jump => Box::new(SlotTable::jump(nodes)),
+        }
+    }
+}
+
+/// A mapping of keys to virtual nodes.
+pub trait Partitioner: Send + Sync {
+    /// The virtual node that owns a key, or `None` if there are no virtual
+    /// nodes.
+    fn owner(&self, key: &KeyHash) -> Option<&VirtualNodeId>;
+
+    /// Every range of key hashes with a single owner, as the start of each
+    /// range in sorted order. Each range extends up to the start of the next
+    /// one, and the last one wraps around to the first.
+    fn ranges(&self) -> Vec<(KeyHash, VirtualNodeId)>;
+
+    /// The virtual nodes in order of preference for a key, starting with its
+    /// owner. A virtual node may appear more than once.
+    fn walk<'a>(&'a self, key: &KeyHash) -> Box<dyn Iterator<Item = &'a VirtualNodeId> + 'a>;
+}
+
+/// A range of key hashes, from `start` inclusive to `end` exclusive, wrapping
+/// around if `end` is not after `start`. A range with `start == end` covers
+/// every key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRange {
+    pub start: KeyHash,
+    pub end: KeyHash,
+}
+
+impl KeyRange {
+    pub fn contains(&self, key: &KeyHash) -> bool {
+        if self.start < self.end {
+            self.start <= *key && *key < self.end
+        } else {
+            self.start <= *key || *key < self.end
+        }
+    }
+
+    /// The approximate fraction of all keys that fall into this range.
+    pub fn fraction(&self) -> f64 {
+        let prefix = |x: &KeyHash| u64::from_be_bytes(x[..8].try_into().unwrap());
+        if self.start == self.end {
+            return 1.0;
+        }
+        let width = prefix(&self.end).wrapping_sub(prefix(&self.start));
+        width as f64 / 2f64.powi(64)
+    }
+}
+
+/// A range of keys that changes owner between two partitioners.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub range: KeyRange,
+    pub from: Option<VirtualNodeId>,
+    pub to: Option<VirtualNodeId>,
+}
+
+/// Every range of keys whose owner differs between two partitioners.
+pub fn moves(old: &dyn Partitioner, new: &dyn Partitioner) -> Vec<Move> {
+    let mut points: Vec<KeyHash> = (old.ranges().into_iter())
+        .chain(new.ranges())
+        .map(|(x, _)| x)
+        .collect();
+    points.sort();
+    points.dedup();
+
+    let mut res: Vec<Move> = Vec::new();
+    for (i, start) in points.iter().enumerate() {
+        let end = points[(i + 1) % points.len()];
+        let from = old.owner(start);
+        let to = new.owner(start);
+        if from == to {
+            continue;
+        }
+        // Merge with the previous move if it's between the same nodes.
+        if let Some(last) = res.last_mut()
+            && last.range.end == *start
+            && last.from.as_ref() == from
+            && last.to.as_ref() == to
+        {
+            last.range.end = end;
+            continue;
+        }
+        res.push(Move {
+            range: KeyRange { start: *start, end },
+            from: from.cloned(),
+            to: to.cloned(),
+        });
+    }
+    res
+}
+
+impl Partitioner for HashRing {
+    fn owner(&self, key: &KeyHash) -> Option<&VirtualNodeId> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.cursor(key).get())
+        }
+    }
+
+    fn ranges(&self) -> Vec<(KeyHash, VirtualNodeId)> {
+        self.points().cloned().collect()
+    }
+
+    fn walk<'a>(&'a self, key: &KeyHash) -> Box<dyn Iterator<Item = &'a VirtualNodeId> + 'a> {
+        if self.is_empty() {
+            return Box::new(std::iter::empty());
+        }
+        let cursors = std::iter::successors(Some(self.cursor(key)), |c| Some(c.next()));
+        Box::new(cursors.take(self.len()).map(|c| c.get()))
+    }
+}
+
+/// The number of bits of the key hash used to pick a slot.
+const SLOT_BITS: u32 = 12;
+
+/// A partitioner that assigns every key to one of a fixed number of slots by
+/// the leading bits of its hash, and assigns slots to virtual nodes.
+struct SlotTable {
+    slots: Vec<VirtualNodeId>,
+}
+
+impl SlotTable {
+    fn rendezvous(nodes: impl Iterator<Item = VirtualNodeId>) -> SlotTable {
+        let nodes: Vec<(u64, VirtualNodeId)> =
+            nodes.map(|vn| (Self::seed(&vn.as_sha256()), vn)).collect();
+        if nodes.is_empty() {
+            return SlotTable { slots: Vec::new() };
+        }
+        let slots = (0..1u64 << SLOT_BITS)
+            .map(|slot| {
+                let (_, vn) = (nodes.iter())
+                    .max_by_key(|(seed, vn)| (mix(seed ^ mix(slot)), vn))
+                    .unwrap();
+                vn.clone()
+            })
+            .collect();
+        SlotTable { slots }
+    }
+
+    fn jump(nodes: impl Iterator<Item = VirtualNodeId>) -> SlotTable {
+        let mut nodes: Vec<VirtualNodeId> = nodes.collect();
+        nodes.sort();
+        if nodes.is_empty() {
+            return SlotTable { slots: Vec::new() };
+        }
+        let slots = (0..1u64 << SLOT_BITS)
+            .map(|slot| nodes[jump_hash(mix(slot), nodes.len())].clone())
+            .collect();
+        SlotTable { slots }
+    }
+
+    fn seed(hash: &KeyHash) -> u64 {
+        u64::from_be_bytes(hash[..8].try_into().unwrap())
+    }
+
+    fn slot_of(key: &KeyHash) -> usize {
+        (Self::seed(key) >> (64 - SLOT_BITS)) as usize
+    }
+
+    fn slot_start(slot: usize) -> KeyHash {
+        let mut res = [0; 32];
+        res[..8].copy_from_slice(&((slot as u64) << (64 - SLOT_BITS)).to_be_bytes());
+        res
+    }
+}
+
+impl Partitioner for SlotTable {
+    fn owner(&self, key: &KeyHash) -> Option<&VirtualNodeId> {
+        self.slots.get(Self::slot_of(key))
+    }
+
+    fn ranges(&self) -> Vec<(KeyHash, VirtualNodeId)> {
+        let mut res: Vec<(KeyHash, VirtualNodeId)> = Vec::new();
+        for (i, vn) in self.slots.iter().enumerate() {
+            if res.last().is_none_or(|(_, last)| last != vn) {
+                res.push((Self::slot_start(i), vn.clone()));
+            }
+        }
+        res
+    }
+
+    fn walk<'a>(&'a self, key: &KeyHash) -> Box<dyn Iterator<Item = &'a VirtualNodeId> + 'a> {
+        let n = self.slots.len();
+        let start = Self::slot_of(key);
+        Box::new((0..n).map(move |i| &self.slots[(start + i) % n]))
+    }
+}
+
+/// A 64-bit mixing function (splitmix64's finalizer).
+fn mix(x: u64) -> u64 {
+    let x = x.wrapping_add(0x9e3779b97f4a7c15);
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+/// Lamping and Veach's jump consistent hash.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+/// Where keys live under a ring config, taking any update in progress into
+/// account.
+pub struct Placement {
+    nodes: HashMap<VirtualNodeId, NetworkId>,
+    labels: BTreeMap<NetworkId, Labels>,
+    current: Box<dyn Partitioner>,
+    next: Option<Box<dyn Partitioner>>,
+}
+
+/// The nodes chosen to hold copies of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replicas {
+    /// The chosen nodes, starting with the owner.
+    pub nodes: Vec<NetworkId>,
+    /// Set if the nodes could not be spread across failure domains as
+    /// requested, or there are fewer nodes than requested.
+    pub unmet: bool,
+}
+
+impl Placement {
+    pub fn new(cf: &RingConfig) -> Placement {
+        let current = cf.partitioner.build(cf.nodes.keys().cloned());
+        let next = cf
+            .update
+            .as_ref()
+            .map(|_| cf.partitioner.build(cf.updated_nodes().into_keys()));
+        let mut nodes = cf.nodes.clone();
+        if let Some(RingUpdateConfig::ToAdd { vn, ni }) = cf.update.as_ref() {
+            nodes.insert(vn.clone(), ni.clone());
+        }
+        Placement {
+            nodes,
+            labels: cf.labels.clone(),
+            current,
+            next,
+        }
+    }
+
+    /// Every range of key hashes with a single owner. See
+    /// [`Partitioner::ranges`].
+    pub fn ranges(&self) -> Vec<(KeyHash, VirtualNodeId)> {
+        self.current.ranges()
+    }
+
+    /// Pick up to `n` distinct nodes for a key, starting with its owner and
+    /// following the partitioner's order of preference. A node is skipped if
+    /// it shares the value of any of the `spread_by` labels with a node that
+    /// has already been picked. If that leaves fewer than `n` nodes, the
+    /// skipped nodes are used after all, in order, and the result is marked
+    /// as unmet.
+    pub fn replicas(&self, key: &KeyHash, n: usize, spread_by: &[String]) -> Replicas {
+        let mut nodes: Vec<NetworkId> = Vec::new();
+        let mut skipped: Vec<NetworkId> = Vec::new();
+        for vn in self.current.walk(key) {
+            if nodes.len() >= n {
+                break;
+            }
+            let Some(ni) = self.nodes.get(vn) else {
+                continue;
+            };
+            if nodes.contains(ni) || skipped.contains(ni) {
+                continue;
+            }
+            if nodes.iter().any(|x| self.same_domain(x, ni, spread_by)) {
+                skipped.push(ni.clone());
+            } else {
+                nodes.push(ni.clone());
+            }
+        }
+        let unmet = nodes.len() < n;
+        nodes.extend(skipped.into_iter().take(n.saturating_sub(nodes.len())));
+        Replicas { nodes, unmet }
+    }
+
+    /// Whether two nodes share the value of any of the given labels. Nodes
+    /// without a label never share it.
+    fn same_domain(&self, a: &NetworkId, b: &NetworkId, labels: &[String]) -> bool {
+        let (Some(a), Some(b)) = (self.labels.get(a), self.labels.get(b)) else {
+            return false;
+        };
+        labels
+            .iter()
+            .any(|l| a.get(l).is_some_and(|x| b.get(l) == Some(x)))
+    }
+
+    /// The network ID that owns a key.
+    pub fn owner(&self, key: &KeyHash) -> Option<&NetworkId> {
+        self.current.owner(key).and_then(|vn| self.nodes.get(vn))
+    }
+
+    /// The virtual node that owns a key.
+    pub fn owner_vn(&self, key: &KeyHash) -> Option<&VirtualNodeId> {
+        self.current.owner(key)
+    }
+
+    /// If the update in progress moves a key to a different network ID, that
+    /// network ID.
+    pub fn moving_to(&self, key: &KeyHash) -> Option<&NetworkId> {
+        let next = self
+            .next
+            .as_ref()?
+            .owner(key)
+            .and_then(|vn| self.nodes.get(vn));
+        if next != self.owner(key) { next } else { None }
+    }
+
+    /// The network IDs that may legitimately hold a copy of a key: the owner
+    /// and, if the key is being moved, the node it is being moved to.
+    pub fn holders(&self, key: &KeyHash) -> Vec<NetworkId> {
+        let owner = self.owner(key).cloned();
+        let moving_to = self.moving_to(key).cloned();
+        owner.into_iter().chain(moving_to).collect()
+    }
+
+    /// The network IDs that have keys to move for the update in progress.
+    pub fn sources(&self) -> BTreeSet<NetworkId> {
+        let Some(next) = self.next.as_ref() else {
+            return BTreeSet::new();
+        };
+        moves(self.current.as_ref(), next.as_ref())
+            .into_iter()
+            .filter(|m| {
+                let from = m.from.as_ref().and_then(|vn| self.nodes.get(vn));
+                let to = m.to.as_ref().and_then(|vn| self.nodes.get(vn));
+                from != to
+            })
+            .flat_map(|m| m.from.and_then(|vn| self.nodes.get(&vn).cloned()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::Digest;
+
+    use super::*;
+
+    const KINDS: [PartitionerKind; 3] = [
+        PartitionerKind::Ring,
+        PartitionerKind::Rendezvous,
+        PartitionerKind::Jump,
+    ];
+
+    fn vns(names: &[&str]) -> Vec<VirtualNodeId> {
+        names.iter().map(|x| VirtualNodeId(x.to_string())).collect()
+    }
+
+    fn key(i: u64) -> KeyHash {
+        sha2::Sha256::digest(i.to_be_bytes()).into()
+    }
+
+    fn key_at(prefix: u64) -> KeyHash {
+        let mut res = [0; 32];
+        res[..8].copy_from_slice(&prefix.to_be_bytes());
+        res
+    }
+
+    /// Some arbitrary keys, plus the first key of every range of both
+    /// partitioners, where an off-by-one would show up.
+    fn sample_keys(a: &dyn Partitioner, b: &dyn Partitioner) -> Vec<KeyHash> {
+        (0..2000)
+            .map(key)
+            .chain(a.ranges().into_iter().map(|(x, _)| x))
+            .chain(b.ranges().into_iter().map(|(x, _)| x))
+            .collect()
+    }
+
+    /// Check `moves` against the owner of every sample key, computed directly.
+    fn check_moves(kind: PartitionerKind, old: &[VirtualNodeId], new: &[VirtualNodeId]) {
+        let old = kind.build(old.iter().cloned());
+        let new = kind.build(new.iter().cloned());
+        let moves = moves(old.as_ref(), new.as_ref());
+        for k in sample_keys(old.as_ref(), new.as_ref()) {
+            let (from, to) = (old.owner(&k), new.owner(&k));
+            let found: Vec<&Move> = moves.iter().filter(|m| m.range.contains(&k)).collect();
+            if from == to {
+                assert!(
+                    found.is_empty(),
+                    "{kind:?}: {k:?} has no move, got {found:?}"
+                );
+            } else {
+                assert_eq!(found.len(), 1, "{kind:?}: {k:?} should have one move");
+                assert_eq!(found[0].from.as_ref(), from, "{kind:?}: {k:?}");
+                assert_eq!(found[0].to.as_ref(), to, "{kind:?}: {k:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn empty_partitioner_owns_nothing() {
+        for kind in KINDS {
+            let p = kind.build(std::iter::empty());
+            assert_eq!(p.owner(&key(0)), None, "{kind:?}");
+            assert!(p.ranges().is_empty(), "{kind:?}");
+            assert_eq!(p.walk(&key(0)).count(), 0, "{kind:?}");
+            assert!(moves(p.as_ref(), p.as_ref()).is_empty(), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn single_node_owns_everything() {
+        let vn = vns(&["a"]);
+        for kind in KINDS {
+            let p = kind.build(vn.iter().cloned());
+            for i in 0..100 {
+                assert_eq!(p.owner(&key(i)), Some(&vn[0]), "{kind:?}");
+            }
+            assert_eq!(p.owner(&[0; 32]), Some(&vn[0]), "{kind:?}");
+            assert_eq!(p.owner(&[0xff; 32]), Some(&vn[0]), "{kind:?}");
+            assert_eq!(p.ranges().len(), 1, "{kind:?}");
+
+            let empty = kind.build(std::iter::empty());
+            let moves = moves(empty.as_ref(), p.as_ref());
+            assert_eq!(moves.len(), 1, "{kind:?}");
+            assert_eq!(moves[0].range.start, moves[0].range.end, "{kind:?}");
+            assert_eq!(moves[0].range.fraction(), 1.0, "{kind:?}");
+            assert_eq!(moves[0].from, None, "{kind:?}");
+            assert_eq!(moves[0].to.as_ref(), Some(&vn[0]), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn key_range_wraps_around() {
+        let range = KeyRange {
+            start: key_at(0xf000_0000_0000_0000),
+            end: key_at(0x1000_0000_0000_0000),
+        };
+        assert!(range.contains(&key_at(0xf000_0000_0000_0000)));
+        assert!(range.contains(&[0xff; 32]));
+        assert!(range.contains(&[0; 32]));
+        assert!(!range.contains(&key_at(0x1000_0000_0000_0000)));
+        assert!(!range.contains(&key_at(0x8000_0000_0000_0000)));
+        assert_eq!(range.fraction(), 0.125);
+
+        let inner = KeyRange {
+            start: range.end,
+            end: range.start,
+        };
+        assert!(inner.contains(&key_at(0x8000_0000_0000_0000)));
+        assert!(!inner.contains(&[0; 32]));
+        assert_eq!(inner.fraction(), 0.875);
+
+        let full = KeyRange {
+            start: range.start,
+            end: range.start,
+        };
+        assert!(full.contains(&[0; 32]));
+        assert!(full.contains(&[0xff; 32]));
+        assert!(full.contains(&range.start));
+        assert_eq!(full.fraction(), 1.0);
+    }
+
+    #[test]
+    fn moves_match_owners_on_add_and_remove() {
+        let old = vns(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let mut added = old.clone();
+        added.push(VirtualNodeId("i".to_owned()));
+        let mut removed = old.clone();
+        removed.remove(3);
+        for kind in KINDS {
+            check_moves(kind, &old, &added);
+            check_moves(kind, &added, &old);
+            check_moves(kind, &old, &removed);
+            check_moves(kind, &removed, &old);
+        }
+    }
+
+    #[test]
+    fn adding_moves_keys_only_to_the_new_node() {
+        // For jump hashing this only holds when the new node sorts last.
+        let old = vns(&["a", "b", "c", "d", "e", "f", "g", "h"]);
+        let mut new = old.clone();
+        new.push(VirtualNodeId("i".to_owned()));
+        for kind in KINDS {
+            let old = kind.build(old.iter().cloned());
+            let new = kind.build(new.iter().cloned());
+            let moves = moves(old.as_ref(), new.as_ref());
+            assert!(!moves.is_empty(), "{kind:?}");
+            for m in moves {
+                assert_eq!(m.to, Some(VirtualNodeId("i".to_owned())), "{kind:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn jump_hash_is_consistent() {
+        for i in 0..1000 {
+            let k = mix(i);
+            assert_eq!(jump_hash(k, 1), 0);
+            for n in 1..32 {
+                let (a, b) = (jump_hash(k, n), jump_hash(k, n + 1));
+                assert!(a < n);
+                assert!(b == a || b == n, "key {k} moved from {a} to {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn slot_tables_cover_every_slot() {
+        let nodes = vns(&["a", "b", "c"]);
+        for table in [
+            SlotTable::rendezvous(nodes.iter().cloned()),
+            SlotTable::jump(nodes.iter().cloned()),
+        ] {
+            assert_eq!(table.slots.len(), 1 << SLOT_BITS);
+            for vn in nodes.iter() {
+                assert!(table.slots.contains(vn), "{vn:?} owns no slots");
+            }
+            assert_eq!(table.owner(&[0; 32]), Some(&table.slots[0]));
+            assert_eq!(table.owner(&[0xff; 32]), table.slots.last());
+        }
+    }
+
+    #[test]
+    fn sources_own_the_keys_being_moved() {
+        for kind in KINDS {
+            let nodes: HashMap<VirtualNodeId, NetworkId> = (0..8)
+                .map(|i| {
+                    (
+                        VirtualNodeId(format!("v{i}")),
+                        NetworkId(format!("n{}", i % 4)),
+                    )
+                })
+                .collect();
+            let cf = RingConfig {
+                nodes,
+                update: Some(RingUpdateConfig::ToAdd {
+                    vn: VirtualNodeId("v8".to_owned()),
+                    ni: NetworkId("n4".to_owned()),
+                }),
+                epoch: 1,
+                partitioner: kind,
+                labels: BTreeMap::new(),
+            };
+            let placement = Placement::new(&cf);
+            let sources = placement.sources();
+            assert!(!sources.is_empty(), "{kind:?}");
+            assert!(!sources.contains(&NetworkId("n4".to_owned())), "{kind:?}");
+
+            let mut moving = BTreeSet::new();
+            for k in (0..2000).map(key) {
+                if let Some(to) = placement.moving_to(&k) {
+                    assert_eq!(to, &NetworkId("n4".to_owned()), "{kind:?}");
+                    moving.insert(placement.owner(&k).unwrap().clone());
+                }
+            }
+            assert!(moving.is_subset(&sources), "{kind:?}");
+        }
+    }
+}
@@ -1,15 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use amimono::runtime::Location;
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 
-use crate::util::hex::Hex;
+use crate::{
+    crdt::partition::{KeyHash, PartitionerKind},
+    util::hex::Hex,
+};
 
 /// Types that can be used as keys in the hash ring
 pub trait RingKey {
+    /// The key's position on the ring. This is called on every lookup, so
+    /// keys that are looked up repeatedly should cache it.
     fn as_sha256(&self) -> [u8; 32];
 
+    /// The key's position on the ring as a hex string, for display.
     fn as_sha256_string(&self) -> String {
         format!("{}", Hex(self.as_sha256()))
     }
@@ -37,6 +43,9 @@ impl NetworkId {
     }
 }
 
+/// Labels attached to a node, such as its zone or rack.
+pub type Labels = BTreeMap<String, String>;
+
 /// A full configuration of a consistent hash ring.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct RingConfig {
@@ -46,9 +55,23 @@ pub struct RingConfig {
 
     /// In-progress modification.
     pub update: Option<RingUpdateConfig>,
+
+    /// Incremented by the controller every time a change to `nodes` is
+    /// committed. Used to pick an authoritative config when nodes disagree.
+    #[serde(default)]
+    pub epoch: u64,
+
+    /// How keys are mapped to virtual nodes.
+    #[serde(default)]
+    pub partitioner: PartitionerKind,
+
+    /// Labels for the nodes in the ring, used to spread replicas across
+    /// failure domains.
+    #[serde(default)]
+    pub labels: BTreeMap<NetworkId, Labels>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum RingUpdateConfig {
     ToAdd { vn: VirtualNodeId, ni: NetworkId },
@@ -60,6 +83,22 @@ impl RingConfig {
     pub fn network_id(&self, vn: &VirtualNodeId) -> Option<&NetworkId> {
         self.nodes.get(vn)
     }
+
+    /// The virtual nodes as they will be once the update in progress, if any,
+    /// is finished.
+    pub fn updated_nodes(&self) -> HashMap<VirtualNodeId, NetworkId> {
+        let mut nodes = self.nodes.clone();
+        match self.update.as_ref() {
+            Some(RingUpdateConfig::ToAdd { vn, ni }) => {
+                nodes.insert(vn.clone(), ni.clone());
+            }
+            Some(RingUpdateConfig::ToRemove { vn, .. }) => {
+                nodes.remove(vn);
+            }
+            None => {}
+        }
+        nodes
+    }
 }
 
 /// A queryable hash ring data structure.
@@ -67,7 +106,7 @@ pub struct HashRing {
     /// A sorted list of (hash, node) pairs, where each item represents a key
     /// range starting from the given hash inclusively and continuing up to the
     /// next hash in the Vec (or wrapping around)
-    data: Vec<(String, VirtualNodeId)>,
+    data: Vec<(KeyHash, VirtualNodeId)>,
 }
 
 impl HashRing {
@@ -78,12 +117,32 @@ impl HashRing {
 
     /// Create a new hash ring from a list of virtual nodes.
     pub fn from_nodes(nodes: impl Iterator<Item = VirtualNodeId>) -> HashRing {
-        let mut data: Vec<(String, VirtualNodeId)> =
-            nodes.map(|n| (n.as_sha256_string(), n)).collect();
+        let mut data: Vec<(KeyHash, VirtualNodeId)> = nodes.map(|n| (n.as_sha256(), n)).collect();
         data.sort();
         HashRing { data }
     }
 
+    /// The number of virtual nodes in the ring.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Whether the ring has no virtual nodes at all. Most queries on an empty
+    /// ring panic.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// The virtual nodes in the ring, in no particular order.
+    pub fn nodes(&self) -> impl Iterator<Item = &VirtualNodeId> {
+        self.data.iter().map(|(_, vn)| vn)
+    }
+
+    /// The virtual nodes in the ring along with their hashes, in ring order.
+    pub fn points(&self) -> impl Iterator<Item = &(KeyHash, VirtualNodeId)> {
+        self.data.iter()
+    }
+
     /// A cursor for navigating the hash ring, starting at a given point.
     pub fn cursor(&'_ self, start: &impl RingKey) -> HashRingCursor<'_> {
         HashRingCursor::new(self, start)
@@ -105,7 +164,7 @@ impl<'r> HashRingCursor<'r> {
     /// Create a new hash ring cursor pointing at the range in which the given
     /// key falls.
     pub fn new(ring: &'r HashRing, at: &impl RingKey) -> HashRingCursor<'r> {
-        let hash = at.as_sha256_string();
+        let hash = at.as_sha256();
         let n = ring.data.len();
         let i = match ring.data.binary_search_by(|x| x.0.cmp(&hash)) {
             Ok(i) => i,
@@ -119,6 +178,11 @@ impl<'r> HashRingCursor<'r> {
         &self.ring.data[self.i].1
     }
 
+    /// Get the hash at which the current key range starts.
+    pub fn hash(&self) -> &'r KeyHash {
+        &self.ring.data[self.i].0
+    }
+
     /// Get a cursor representing the next range.
     pub fn next(&self) -> HashRingCursor<'r> {
         let n = self.ring.data.len();
@@ -128,32 +192,36 @@ impl<'r> HashRingCursor<'r> {
         }
     }
 
+    /// Get a cursor representing the previous range.
+    pub fn prev(&self) -> HashRingCursor<'r> {
+        let n = self.ring.data.len();
+        HashRingCursor {
+            ring: self.ring,
+            i: (self.i + n - 1) % n,
+        }
+    }
+
     /// Get a range object for the current cursor position
     pub fn range(&self) -> HashRingRange {
-        let a = self.get().clone();
-        let b = self.next().get().clone();
-        HashRingRange::new(a, b)
+        let next = self.next();
+        HashRingRange {
+            a_hash: *self.hash(),
+            b_hash: *next.hash(),
+            a: self.get().clone(),
+            b: next.get().clone(),
+        }
     }
 }
 
 /// A range in a hash ring, represented by a pair of virtual nodes.
 pub struct HashRingRange {
-    a_hash: String,
-    b_hash: String,
+    a_hash: KeyHash,
+    b_hash: KeyHash,
     a: VirtualNodeId,
     b: VirtualNodeId,
 }
 
 impl HashRingRange {
-    fn new(a: VirtualNodeId, b: VirtualNodeId) -> HashRingRange {
-        HashRingRange {
-            a_hash: a.as_sha256_string(),
-            b_hash: b.as_sha256_string(),
-            a,
-            b,
-        }
-    }
-
     /// Get the virtual node ID for the start of the range
     pub fn start(&self) -> &VirtualNodeId {
         &self.a
@@ -161,21 +229,26 @@ impl HashRingRange {
 
     /// Tests whether the given point is contained in this range
     pub fn contains(&self, pt: &impl RingKey) -> bool {
+        self.contains_hash(&pt.as_sha256())
+    }
+
+    /// Tests whether the given hash is contained in this range
+    pub fn contains_hash(&self, x_hash: &KeyHash) -> bool {
         use std::cmp::Ordering::*;
-        let x_hash = pt.as_sha256_string();
         match self.a_hash.cmp(&self.b_hash) {
             Equal => false, // range is empty. this should never happen, though
-            Less => self.a_hash <= x_hash && x_hash < self.b_hash,
-            Greater => self.a_hash <= x_hash || x_hash < self.b_hash,
+            Less => self.a_hash <= *x_hash && *x_hash < self.b_hash,
+            Greater => self.a_hash <= *x_hash || *x_hash < self.b_hash,
         }
     }
 
     /// Creates a new range with an updated starting node ID
     pub fn trim_start(&self, vn: VirtualNodeId) -> HashRingRange {
-        assert!(self.contains(&vn));
+        let a_hash = vn.as_sha256();
+        assert!(self.contains_hash(&a_hash));
         HashRingRange {
-            a_hash: vn.as_sha256_string(),
-            b_hash: self.b_hash.clone(),
+            a_hash,
+            b_hash: self.b_hash,
             a: vn,
             b: self.b.clone(),
         }
@@ -3,83 +3,186 @@ use amimono::{
     rpc::{RpcError, RpcResult},
     runtime::{self, Location},
 };
-use futures::join;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Mutex,
+    time::Duration,
+};
+
+use futures::{future::join_all, join};
 use rand::seq::SliceRandom;
 use sha2::Digest;
 
 const TTL: usize = 8;
 
 use crate::crdt::{
+    ControllerSettings,
+    admin::{ControllerControls, ControllerPlan, KeyPlacement},
+    hash_tag_in_scope,
+    lease::LeaseTable,
     merge_in_scope,
-    ring::{HashRing, NetworkId, RingConfig, RingKey, RingUpdateConfig},
-    storage::{self, StorageInstance},
+    partition::{KeyHash, Placement},
+    ring::{NetworkId, RingConfig, RingKey},
+    storage::{self, StorageInstance, TransferAck, TransferBatch},
 };
 
 mod ops {
-    use crate::crdt::ring::RingConfig;
+    use crate::crdt::{
+        admin::{ControllerControls, ControllerPlan, KeyPlacement},
+        ring::RingConfig,
+        storage::{TransferAck, TransferBatch},
+    };
 
     amimono::rpc_ops! {
         // router endpoints
         fn get(path: Vec<String>, scope: String, key: String) -> Option<Vec<u8>>;
         fn put(path: Vec<String>, scope: String, key: String, data: Vec<u8>) -> Vec<u8>;
+        fn put_delta(path: Vec<String>, scope: String, key: String, data: Vec<u8>) -> ();
+        fn put_many(path: Vec<String>, scope: String, items: Vec<(String, Vec<u8>)>) -> Vec<Vec<u8>>;
+        fn explain(path: Vec<String>, scope: String, key: String) -> KeyPlacement;
 
         // storage layer endpoints
         fn get_here(scope: String, key: String) -> Option<Vec<u8>>;
+        fn has_here(scope: String, key: String) -> bool;
         fn put_here(scope: String, key: String, data: Vec<u8>) -> Vec<u8>;
+        fn put_delta_here(scope: String, key: String, data: Vec<u8>) -> ();
+        fn put_many_here(scope: String, items: Vec<(String, Vec<u8>)>) -> Vec<Vec<u8>>;
+        fn put_batch_here(batch: TransferBatch) -> TransferAck;
 
         // controller endpoints
+        fn ping() -> String;
         fn updating() -> bool;
         fn get_ring() -> Option<RingConfig>;
         fn set_ring(ring: RingConfig) -> ();
+        fn lease(candidate: String, ttl_ms: u64) -> String;
+        fn count_orphans(ring: RingConfig) -> u64;
+        fn rehome() -> u64;
+        fn get_controls() -> ControllerControls;
+        fn set_controls(controls: ControllerControls) -> ();
+        fn get_plan() -> Option<ControllerPlan>;
+        fn set_plan(plan: ControllerPlan) -> ();
     }
 }
 
 enum Action {
     Forward(NetworkId),
     Store,
-    StoreAdding(NetworkId),
+    StoreMoving(NetworkId),
 }
 
 pub struct CrdtRouter {
     myself: NetworkId,
     storage: &'static StorageInstance,
     router: CrdtRouterClient,
+    lease: LeaseTable,
+    plan: Mutex<Option<ControllerPlan>>,
+    /// A random ID chosen when the router starts, so that the controller can
+    /// tell when a node has restarted.
+    boot_id: String,
 }
 
 impl CrdtRouter {
-    fn action(&self, ck: &CompositeKey, cf: &RingConfig, ring: &HashRing) -> RpcResult<Action> {
-        use RingUpdateConfig::*;
-
-        let range = ring.range(ck);
-        let vn0 = range.start();
-        let ni0 = cf
-            .network_id(vn0)
-            .ok_or(RpcError::Misc(format!("ring config corrupted: {vn0:?}")))?
-            .clone();
+    fn action(&self, ck: &CompositeKey, placement: &Placement) -> RpcResult<Action> {
+        let hash = ck.as_sha256();
+        let owner = placement.owner(&hash).ok_or(RpcError::Misc(
+            "ring config has no virtual nodes".to_owned(),
+        ))?;
 
-        if ni0 != self.myself {
+        if *owner != self.myself {
             // not my circus, not my monkeys
-            return Ok(Action::Forward(ni0));
+            return Ok(Action::Forward(owner.clone()));
         }
 
-        let action = match &cf.update {
-            Some(ToAdd { vn, ni }) => {
-                if range.contains(vn) && range.trim_start(vn.clone()).contains(ck) {
-                    // this key is being migrated and may already have been moved
-                    Action::StoreAdding(ni.clone())
-                } else {
-                    Action::Store
-                }
-            }
-            Some(ToRemove { .. }) => {
-                todo!()
-            }
+        // If the key is being moved by the update in progress, it may already
+        // have been moved.
+        let action = match placement.moving_to(&hash) {
+            Some(to) => Action::StoreMoving(to.clone()),
             None => Action::Store,
         };
 
         Ok(action)
     }
 
+    /// Describe where a key lives. Called on the key's owner.
+    async fn explain_here(&self, ck: CompositeKey, path: Vec<String>) -> RpcResult<KeyPlacement> {
+        let settings = ControllerSettings::current();
+        let hash = ck.as_sha256();
+        let placement = self
+            .storage
+            .with_ring(|_, placement| {
+                let owner_vn = placement.owner_vn(&hash)?.clone();
+                let owner = placement.owner(&hash)?.clone();
+                let replicas =
+                    placement.replicas(&hash, settings.replicas.max(1), &settings.spread_by);
+                Some(KeyPlacement {
+                    hash: ck.as_sha256_string(),
+                    hash_tag: ck.hash_tag().map(|x| x.to_owned()),
+                    owner_vn,
+                    owner,
+                    replicas: replicas.nodes,
+                    moving_to: placement.moving_to(&hash).cloned(),
+                    path,
+                    copies: BTreeMap::new(),
+                    scope: ck.scope.clone(),
+                    key: ck.key.clone(),
+                })
+            })
+            .await
+            .flatten();
+        let mut placement = placement.ok_or(RpcError::Misc(
+            "ring config has no virtual nodes".to_owned(),
+        ))?;
+
+        let holders: BTreeSet<NetworkId> = (Some(placement.owner.clone()).into_iter())
+            .chain(placement.moving_to.clone())
+            .chain(placement.replicas.iter().cloned())
+            .collect();
+        let results = join_all(holders.iter().map(|ni| {
+            let router = self.router.at(ni.as_location());
+            let (scope, key) = (ck.scope.clone(), ck.key.clone());
+            async move { router.has_here(scope, key).await }
+        }))
+        .await;
+        for (ni, res) in holders.into_iter().zip(results) {
+            if let Err(e) = &res {
+                log::warn!(
+                    "failed to check for {}/{} on {ni:?}: {e:?}",
+                    ck.scope,
+                    ck.key
+                );
+            }
+            placement.copies.insert(ni, res.ok());
+        }
+        Ok(placement)
+    }
+
+    /// Decide what to do with a write to a key, and the path to forward it
+    /// with. Fails if the request has been forwarded too many times.
+    async fn route(
+        &self,
+        path: Vec<String>,
+        ck: &CompositeKey,
+    ) -> RpcResult<(Vec<String>, Action)> {
+        if path.len() >= TTL {
+            return Err(RpcError::Misc(format!("ttl expired: {path:?}")));
+        }
+        let next_path = path
+            .into_iter()
+            .chain(Some(self.myself.0.clone()))
+            .collect();
+
+        let maybe_action = self
+            .storage
+            .with_ring(|_, placement| self.action(ck, placement))
+            .await;
+
+        let action = match maybe_action {
+            Some(a) => a?,
+            None => Action::Forward(self.random_peer().await?),
+        };
+        Ok((next_path, action))
+    }
+
     async fn random_peer(&self) -> RpcResult<NetworkId> {
         // This is needed in the exceptional circumstance where a node has
         // freshly booted up, has no config, and receives a request. The request
@@ -118,11 +221,14 @@ impl ops::Handler for CrdtRouter {
         };
 
         let storage = storage::instance();
-        storage.sync_updater().await;
+        storage.sync_updater(&myself).await;
         CrdtRouter {
             myself,
             storage,
             router: CrdtRouterClient::new(),
+            lease: LeaseTable::new(),
+            plan: Mutex::new(None),
+            boot_id: format!("{:016x}", rand::random::<u64>()),
         }
     }
 
@@ -140,11 +246,11 @@ impl ops::Handler for CrdtRouter {
             .chain(Some(self.myself.0.clone()).into_iter())
             .collect();
 
-        let ck = CompositeKey { scope, key };
+        let ck = CompositeKey::new(scope, key);
 
         let maybe_action = self
             .storage
-            .with_ring(|cf, ring| self.action(&ck, cf, ring))
+            .with_ring(|_, placement| self.action(&ck, placement))
             .await;
 
         let action = match maybe_action {
@@ -162,7 +268,7 @@ impl ops::Handler for CrdtRouter {
 
             Action::Store => self.get_here(ck.scope, ck.key).await,
 
-            Action::StoreAdding(to) => {
+            Action::StoreMoving(to) => {
                 let tgt = self.router.at(to.as_location());
                 let (a, b) = join!(
                     self.get_here(ck.scope.clone(), ck.key.clone()),
@@ -190,19 +296,111 @@ impl ops::Handler for CrdtRouter {
         key: String,
         data: Vec<u8>,
     ) -> RpcResult<Vec<u8>> {
+        let ck = CompositeKey::new(scope, key);
+        match self.route(path, &ck).await? {
+            (next_path, Action::Forward(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put(next_path, ck.scope, ck.key, data)
+                    .await
+            }
+
+            (_, Action::StoreMoving(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put_here(ck.scope, ck.key, data)
+                    .await
+            }
+
+            (_, Action::Store) => self.put_here(ck.scope, ck.key, data).await,
+        }
+    }
+
+    async fn put_delta(
+        &self,
+        path: Vec<String>,
+        scope: String,
+        key: String,
+        data: Vec<u8>,
+    ) -> RpcResult<()> {
+        let ck = CompositeKey::new(scope, key);
+        match self.route(path, &ck).await? {
+            (next_path, Action::Forward(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put_delta(next_path, ck.scope, ck.key, data)
+                    .await
+            }
+
+            (_, Action::StoreMoving(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put_delta_here(ck.scope, ck.key, data)
+                    .await
+            }
+
+            (_, Action::Store) => self.put_delta_here(ck.scope, ck.key, data).await,
+        }
+    }
+
+    async fn put_many(
+        &self,
+        path: Vec<String>,
+        scope: String,
+        items: Vec<(String, Vec<u8>)>,
+    ) -> RpcResult<Vec<Vec<u8>>> {
+        let Some((first, _)) = items.first() else {
+            return Ok(Vec::new());
+        };
+        let ck = CompositeKey::new(scope, first.clone());
+        let hash = ck.as_sha256();
+        if let Some((key, _)) =
+            (items.iter()).find(|(k, _)| CompositeKey::hash(&ck.scope, k) != hash)
+        {
+            return Err(RpcError::Misc(format!(
+                "{first:?} and {key:?} in {} are not colocated",
+                ck.scope
+            )));
+        }
+
+        match self.route(path, &ck).await? {
+            (next_path, Action::Forward(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put_many(next_path, ck.scope, items)
+                    .await
+            }
+
+            (_, Action::StoreMoving(to)) => {
+                self.router
+                    .at(to.as_location())
+                    .put_many_here(ck.scope, items)
+                    .await
+            }
+
+            (_, Action::Store) => self.put_many_here(ck.scope, items).await,
+        }
+    }
+
+    async fn explain(
+        &self,
+        path: Vec<String>,
+        scope: String,
+        key: String,
+    ) -> RpcResult<KeyPlacement> {
         if path.len() >= TTL {
             return Err(RpcError::Misc(format!("ttl expired: {path:?}")));
         }
-        let next_path = path
+        let next_path: Vec<String> = path
             .into_iter()
-            .chain(Some(self.myself.0.clone()).into_iter())
+            .chain(Some(self.myself.0.clone()))
             .collect();
 
-        let ck = CompositeKey { scope, key };
+        let ck = CompositeKey::new(scope, key);
 
         let maybe_action = self
             .storage
-            .with_ring(|cf, ring| self.action(&ck, cf, ring))
+            .with_ring(|_, placement| self.action(&ck, placement))
             .await;
 
         let action = match maybe_action {
@@ -214,18 +412,10 @@ impl ops::Handler for CrdtRouter {
             Action::Forward(to) => {
                 self.router
                     .at(to.as_location())
-                    .put(next_path, ck.scope, ck.key, data)
-                    .await
-            }
-
-            Action::StoreAdding(to) => {
-                self.router
-                    .at(to.as_location())
-                    .put_here(ck.scope, ck.key, data)
+                    .explain(next_path, ck.scope, ck.key)
                     .await
             }
-
-            Action::Store => self.put_here(ck.scope, ck.key, data).await,
+            Action::Store | Action::StoreMoving(_) => self.explain_here(ck, next_path).await,
         }
     }
 
@@ -243,6 +433,45 @@ impl ops::Handler for CrdtRouter {
             .map_err(|e| RpcError::Misc(format!("put failed: {e}")))
     }
 
+    async fn put_delta_here(&self, scope: String, key: String, data: Vec<u8>) -> RpcResult<()> {
+        // The merged value isn't sent back, since it is usually much larger
+        // than the delta.
+        self.storage
+            .put_here(&scope, &key, &data)
+            .await
+            .map(|_| ())
+            .map_err(|e| RpcError::Misc(format!("put delta failed: {e}")))
+    }
+
+    async fn has_here(&self, scope: String, key: String) -> RpcResult<bool> {
+        self.storage
+            .has_here(&scope, &key)
+            .await
+            .map_err(|e| RpcError::Misc(format!("has failed: {e}")))
+    }
+
+    async fn put_many_here(
+        &self,
+        scope: String,
+        items: Vec<(String, Vec<u8>)>,
+    ) -> RpcResult<Vec<Vec<u8>>> {
+        self.storage
+            .put_many_here(&scope, &items)
+            .await
+            .map_err(|e| RpcError::Misc(format!("put many failed: {e}")))
+    }
+
+    async fn put_batch_here(&self, batch: TransferBatch) -> RpcResult<TransferAck> {
+        self.storage
+            .put_batch_here(&batch)
+            .await
+            .map_err(|e| RpcError::Misc(format!("put batch failed: {e}")))
+    }
+
+    async fn ping(&self) -> RpcResult<String> {
+        Ok(self.boot_id.clone())
+    }
+
     async fn updating(&self) -> RpcResult<bool> {
         Ok(self.storage.updating().await)
     }
@@ -253,21 +482,98 @@ impl ops::Handler for CrdtRouter {
 
     async fn set_ring(&self, ring: RingConfig) -> RpcResult<()> {
         self.storage.set_ring_config(ring).await;
-        self.storage.sync_updater().await;
+        self.storage.sync_updater(&self.myself).await;
+        Ok(())
+    }
+
+    async fn lease(&self, candidate: String, ttl_ms: u64) -> RpcResult<String> {
+        Ok(self.lease.request(candidate, Duration::from_millis(ttl_ms)))
+    }
+
+    async fn count_orphans(&self, ring: RingConfig) -> RpcResult<u64> {
+        self.storage
+            .count_orphans(&self.myself, &ring)
+            .await
+            .map_err(|e| RpcError::Misc(format!("failed to count orphans: {e}")))
+    }
+
+    async fn rehome(&self) -> RpcResult<u64> {
+        self.storage
+            .rehome(&self.myself)
+            .await
+            .map_err(|n| RpcError::Misc(format!("failed to rehome {n} keys")))
+    }
+
+    async fn get_controls(&self) -> RpcResult<ControllerControls> {
+        Ok(self.storage.get_controls().await)
+    }
+
+    async fn set_controls(&self, controls: ControllerControls) -> RpcResult<()> {
+        self.storage.merge_controls(controls).await;
+        Ok(())
+    }
+
+    async fn get_plan(&self) -> RpcResult<Option<ControllerPlan>> {
+        Ok(self.plan.lock().expect("failed to get plan lock").clone())
+    }
+
+    async fn set_plan(&self, plan: ControllerPlan) -> RpcResult<()> {
+        *self.plan.lock().expect("failed to get plan lock") = Some(plan);
         Ok(())
     }
 }
 
+/// A key within a scope. The key's position on the ring is computed once, when
+/// the key is created.
+#[derive(Debug, Clone)]
 pub struct CompositeKey {
-    pub scope: String,
-    pub key: String,
+    scope: String,
+    key: String,
+    hash: KeyHash,
+}
+
+impl CompositeKey {
+    /// Create a key. If the scope uses hash tags and the key has one, the key
+    /// is placed by its hash tag alone, otherwise by its scope and key.
+    pub fn new(scope: String, key: String) -> CompositeKey {
+        let hash = CompositeKey::hash(&scope, &key);
+        CompositeKey { scope, key, hash }
+    }
+
+    /// The position on the ring of a key within a scope.
+    pub fn hash(scope: &str, key: &str) -> KeyHash {
+        match hash_tag_in_scope(scope, key) {
+            Some(tag) => sha2::Sha256::new()
+                .chain_update([0])
+                .chain_update(tag)
+                .finalize()
+                .into(),
+            None => sha2::Sha256::new()
+                .chain_update(scope)
+                .chain_update([0])
+                .chain_update(key)
+                .finalize()
+                .into(),
+        }
+    }
+
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// The hash tag that places this key, if any.
+    pub fn hash_tag(&self) -> Option<&str> {
+        hash_tag_in_scope(&self.scope, &self.key)
+    }
 }
 
 impl RingKey for CompositeKey {
     fn as_sha256(&self) -> [u8; 32] {
-        let data = format!("{}\0{}", self.scope, self.key);
-        let hash = sha2::Sha256::digest(data);
-        hash.try_into().unwrap()
+        self.hash
     }
 }
 
@@ -0,0 +1,92 @@
+//! Settings for the controller.
+
+use std::{
+    collections::BTreeMap,
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use crate::crdt::{
+    membership::Membership,
+    partition::PartitionerKind,
+    ring::{Labels, NetworkId},
+};
+
+/// Tunables for the controller. The defaults are suitable for most clusters.
+///
+/// Settings must be installed with [`install`][Self::install] before
+/// application startup.
+#[derive(Debug, Clone)]
+pub struct ControllerSettings {
+    /// A node in the ring that hasn't answered the controller for this long
+    /// is suspected to have failed. The controller makes no changes to the
+    /// ring while any node is suspect.
+    pub suspect_after: Duration,
+
+    /// A node in the ring that hasn't answered the controller for this long is
+    /// declared dead, and its virtual nodes are reassigned to the surviving
+    /// nodes. Data that was only stored on the dead node is lost, so this is
+    /// off by default: if `None`, nodes are never declared dead and the
+    /// controller waits indefinitely for an operator to intervene.
+    pub dead_after: Option<Duration>,
+
+    /// How often the controller re-reads each node's ring config, in case it
+    /// was changed behind the controller's back. Each node is refreshed on its
+    /// own schedule, jittered by up to half of this in either direction so
+    /// that refreshes are spread out.
+    pub refresh_interval: Duration,
+
+    /// Where the desired cluster membership comes from.
+    pub membership: Membership,
+
+    /// The partitioner used when bootstrapping a new cluster. The partitioner
+    /// of an existing cluster is part of its ring config, and is only changed
+    /// by a forced bootstrap.
+    pub partitioner: PartitionerKind,
+
+    /// Labels for nodes, such as their zone or rack. Labels in a membership
+    /// manifest take precedence.
+    pub labels: BTreeMap<NetworkId, Labels>,
+
+    /// How many nodes each key should be placed on. Storage is currently not
+    /// replicated, so this is only used to check that the cluster could
+    /// satisfy `spread_by`.
+    pub replicas: usize,
+
+    /// Labels whose values replicas of a key should not share, e.g. `zone`.
+    /// The controller warns when the cluster can't satisfy this.
+    pub spread_by: Vec<String>,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        ControllerSettings {
+            suspect_after: Duration::from_secs(15),
+            dead_after: None,
+            refresh_interval: Duration::from_secs(60),
+            membership: Membership::Discovery,
+            partitioner: PartitionerKind::Ring,
+            labels: BTreeMap::new(),
+            replicas: 1,
+            spread_by: vec!["zone".to_owned()],
+        }
+    }
+}
+
+impl ControllerSettings {
+    /// Install these settings for the controller.
+    pub fn install(self) {
+        self.membership.bind();
+        *SETTINGS.write().expect("failed to get SETTINGS lock") = self;
+    }
+
+    pub(crate) fn current() -> ControllerSettings {
+        SETTINGS
+            .read()
+            .expect("failed to get SETTINGS lock")
+            .clone()
+    }
+}
+
+static SETTINGS: LazyLock<RwLock<ControllerSettings>> =
+    LazyLock::new(|| RwLock::new(ControllerSettings::default()));
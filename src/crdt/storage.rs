@@ -1,4 +1,9 @@
-use std::{io, path::PathBuf, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use amimono::{
     config::{Binding, ComponentConfig},
@@ -6,18 +11,196 @@ use amimono::{
 };
 use futures::future::BoxFuture;
 use lockable::LockPool;
-use tokio::sync::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, RwLock},
+    task::AbortHandle,
+};
 
 use crate::crdt::{
+    Crdt,
+    admin::ControllerControls,
     merge_in_scope,
-    ring::{HashRing, NetworkId, RingConfig, RingUpdateConfig, VirtualNodeId},
+    partition::{KeyHash, Placement},
+    ring::{NetworkId, RingConfig, RingKey, RingUpdateConfig},
     router::{CompositeKey, CrdtRouterClient},
 };
+use crate::util::hex::Hex;
+
+/// The maximum number of values sent in a single transfer batch.
+const TRANSFER_BATCH_ITEMS: usize = 256;
+
+/// The approximate maximum number of bytes of value data sent in a single
+/// transfer batch. A single value larger than this is still sent on its own.
+const TRANSFER_BATCH_BYTES: usize = 1 << 20;
+
+/// The longest wait between attempts to apply the journal of a multi-key
+/// merge.
+const JOURNAL_RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// How many times applying the journal of a multi-key merge is attempted
+/// before the node gives up and exits.
+const JOURNAL_APPLY_ATTEMPTS: u32 = 10;
+
+/// A batch of values moved from one storage node to another during a range
+/// migration.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransferBatch {
+    pub items: Vec<TransferItem>,
+    /// The total size of the data of the items pushed so far, which is only
+    /// tracked by the sender.
+    #[serde(skip)]
+    bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransferItem {
+    pub scope: String,
+    pub key: String,
+    pub data: Vec<u8>,
+}
+
+/// Acknowledgement that every value in a [`TransferBatch`] has been durably
+/// merged by the receiver. The digest is computed by the receiver over the
+/// batch it actually received, so the sender can check it against its own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferAck {
+    pub count: usize,
+    pub digest: String,
+}
+
+impl TransferBatch {
+    fn push(&mut self, scope: String, key: String, data: Vec<u8>) {
+        self.bytes += data.len();
+        self.items.push(TransferItem { scope, key, data });
+    }
+
+    fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.items.len() >= TRANSFER_BATCH_ITEMS || self.bytes >= TRANSFER_BATCH_BYTES
+    }
+
+    /// The acknowledgement a receiver is expected to send for this batch.
+    pub fn ack(&self) -> TransferAck {
+        let mut hasher = sha2::Sha256::new();
+        for item in self.items.iter() {
+            hasher.update(item.scope.as_bytes());
+            hasher.update([0]);
+            hasher.update(item.key.as_bytes());
+            hasher.update([0]);
+            hasher.update((item.data.len() as u64).to_be_bytes());
+            hasher.update(&item.data);
+        }
+        TransferAck {
+            count: self.items.len(),
+            digest: format!("{}", Hex(hasher.finalize())),
+        }
+    }
+}
+
+/// The writes of a multi-key merge, recorded before any of them are made so
+/// that they can be finished if the node stops halfway through.
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// The scope of every key written.
+    scope: String,
+    /// Paths relative to the storage directory, and the data to write there.
+    writes: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl Journal {
+    /// Merge each write with the value already stored there, if any. A journal
+    /// can outlive its batch when removing it fails, and replaying it must not
+    /// undo merges made after it was applied.
+    async fn merge_stored(mut self, storage: &Path) -> io::Result<Journal> {
+        for (path, data) in self.writes.iter_mut() {
+            let path = storage.join(path);
+            if path.exists() {
+                let current = tokio::fs::read(&path).await?;
+                *data = merge_in_scope(&self.scope, &current, data).map_err(io::Error::other)?;
+            }
+        }
+        Ok(self)
+    }
+
+    /// Make every write, and sync the directories written to, so that the
+    /// journal can be removed once this returns.
+    async fn apply(&self, storage: &Path) -> io::Result<()> {
+        let mut dirs = BTreeSet::from([storage.to_owned()]);
+        for (path, data) in self.writes.iter() {
+            let path = storage.join(path);
+            let dir = path.parent().unwrap();
+            tokio::fs::create_dir_all(dir).await?;
+            write_synced(&path, data).await?;
+            dirs.insert(dir.to_owned());
+        }
+        for dir in dirs {
+            sync_dir(&dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Finish any multi-key merges that were interrupted. A journal that can't be
+/// parsed was interrupted while it was being written, before any of its
+/// writes were made, and is discarded.
+async fn replay_journals(root: &Path) {
+    let dir = root.join("journal");
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .expect("failed to create journal directory");
+    let mut entries = tokio::fs::read_dir(&dir)
+        .await
+        .expect("failed to read journal directory");
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        let path = entry.path();
+        let data = tokio::fs::read(&path)
+            .await
+            .expect("failed to read journal");
+        match serde_json::from_slice::<Journal>(&data) {
+            Ok(journal) => {
+                log::info!("replaying {} writes from {path:?}", journal.writes.len());
+                let storage = root.join("storage");
+                journal
+                    .merge_stored(&storage)
+                    .await
+                    .expect("failed to merge journal")
+                    .apply(&storage)
+                    .await
+                    .expect("failed to replay journal");
+            }
+            Err(e) => log::warn!("discarding incomplete journal {path:?}: {e}"),
+        }
+        tokio::fs::remove_file(&path)
+            .await
+            .expect("failed to remove journal");
+    }
+}
+
+async fn write_synced(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = tokio::fs::File::create(path).await?;
+    file.write_all(data).await?;
+    file.sync_all().await
+}
+
+/// Sync a directory, so that the files created in it survive a crash.
+async fn sync_dir(path: &Path) -> io::Result<()> {
+    tokio::fs::File::open(path).await?.sync_all().await
+}
 
 pub struct StorageInstance {
     root: PathBuf,
     ring: RwLock<RingStorage>,
-    updater: Mutex<Option<RingUpdateConfig>>,
+    controls: Mutex<ControllerControls>,
+    updater: Mutex<Option<(RingUpdateConfig, AbortHandle)>>,
+    /// Held for the whole of a rehome, so that a rehome requested while
+    /// another is still running waits for it instead of sending the same keys.
+    rehoming: Mutex<()>,
     files: LockPool<PathBuf>,
 }
 
@@ -27,11 +210,15 @@ impl StorageInstance {
             .await
             .expect("failed to get storage location");
         let ring = RingStorage::load(root.join("ring.json")).await;
+        let controls = load_controls(&root.join("controls.json")).await;
         std::fs::create_dir_all(root.join("storage")).unwrap();
+        replay_journals(&root).await;
         StorageInstance {
             root,
             ring: RwLock::new(ring),
+            controls: Mutex::new(controls),
             updater: Mutex::new(None),
+            rehoming: Mutex::new(()),
             files: LockPool::new(),
         }
     }
@@ -49,6 +236,22 @@ impl StorageInstance {
         self.ring.write().await.set(ring).await;
     }
 
+    pub async fn get_controls(&self) -> ControllerControls {
+        self.controls.lock().await.clone()
+    }
+
+    /// Merge the given controls into the stored ones and persist the result.
+    pub async fn merge_controls(&self, other: ControllerControls) -> ControllerControls {
+        let mut controls = self.controls.lock().await;
+        controls.merge_from(other);
+        let data =
+            serde_json::to_vec_pretty(&*controls).expect("could not convert controls to json");
+        tokio::fs::write(self.root.join("controls.json"), data)
+            .await
+            .expect("write controls failed");
+        controls.clone()
+    }
+
     async fn with_lock<F, T>(&self, scope: &str, key: &str, handle: F) -> T
     where
         F: AsyncFnOnce(PathBuf) -> T,
@@ -62,7 +265,7 @@ impl StorageInstance {
 
     pub async fn with_ring<F, T>(&self, handle: F) -> Option<T>
     where
-        F: FnOnce(&RingConfig, &HashRing) -> T,
+        F: FnOnce(&RingConfig, &Placement) -> T,
     {
         self.ring
             .read()
@@ -83,6 +286,11 @@ impl StorageInstance {
         .await
     }
 
+    pub async fn has_here(&self, scope: &str, key: &str) -> io::Result<bool> {
+        self.with_lock(scope, key, async |path| Ok(path.exists()))
+            .await
+    }
+
     pub async fn put_here(&self, scope: &str, key: &str, data: &[u8]) -> io::Result<Vec<u8>> {
         self.with_lock(scope, key, async |path| {
             if path.exists() {
@@ -99,72 +307,166 @@ impl StorageInstance {
         .await
     }
 
+    /// Merge several values in a scope atomically, and return the merged
+    /// values in the same order.
+    ///
+    /// Every key is locked, in sorted order so that concurrent batches can't
+    /// deadlock, before anything is read, and the locks are held until every
+    /// value is written, so readers see either none or all of the batch. The
+    /// merged values are written to a journal before any of them is written
+    /// in place. If writing them fails partway through, it is retried a few
+    /// times. If it still fails, or the node stops, the journal is kept and
+    /// replayed the next time the node starts; the node exits rather than
+    /// release the locks with the batch half written.
+    pub async fn put_many_here(
+        &self,
+        scope: &str,
+        items: &[(String, Vec<u8>)],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        let mut values: BTreeMap<PathBuf, Vec<u8>> = BTreeMap::new();
+        for (key, data) in items {
+            let path = mk_path(scope, key);
+            let next = match values.remove(&path) {
+                Some(prev) => merge_in_scope(scope, &prev, data).map_err(io::Error::other)?,
+                None => data.clone(),
+            };
+            values.insert(path, next);
+        }
+
+        let mut locks = Vec::new();
+        for path in values.keys() {
+            locks.push(self.files.async_lock(path.clone()).await);
+        }
+
+        let storage = self.root.join("storage");
+        for (path, data) in values.iter_mut() {
+            let path = storage.join(path);
+            if path.exists() {
+                let current = tokio::fs::read(&path).await?;
+                *data = merge_in_scope(scope, &current, data).map_err(io::Error::other)?;
+            }
+        }
+
+        let journal = Journal {
+            scope: scope.to_owned(),
+            writes: values.iter().map(|(p, x)| (p.clone(), x.clone())).collect(),
+        };
+        let journal_path =
+            (self.root.join("journal")).join(format!("{:016x}.json", rand::random::<u64>()));
+        write_synced(&journal_path, &serde_json::to_vec(&journal)?).await?;
+        sync_dir(&self.root.join("journal")).await?;
+
+        // The batch is committed once the journal is written, so a failure to
+        // apply it is retried, with the keys still locked, rather than
+        // returned.
+        let mut delay = Duration::from_millis(100);
+        let mut attempts = 1;
+        while let Err(e) = journal.apply(&storage).await {
+            if attempts >= JOURNAL_APPLY_ATTEMPTS {
+                log::error!(
+                    "failed to apply journal {journal_path:?} after {attempts} attempts, \
+                     exiting so that it is replayed on restart: {e}"
+                );
+                std::process::exit(1);
+            }
+            log::warn!("failed to apply journal {journal_path:?}, retrying: {e}");
+            tokio::time::sleep(delay).await;
+            delay = std::cmp::min(delay * 2, JOURNAL_RETRY_MAX_DELAY);
+            attempts += 1;
+        }
+        // The writes are durable, so a leftover journal only means they are
+        // merged in again on the next start, which changes nothing.
+        if let Err(e) = tokio::fs::remove_file(&journal_path).await {
+            log::warn!("failed to remove applied journal {journal_path:?}: {e}");
+        }
+        std::mem::drop(locks);
+
+        let res = items
+            .iter()
+            .map(|(key, _)| values[&mk_path(scope, key)].clone())
+            .collect();
+        Ok(res)
+    }
+
+    /// Merge every value in a transfer batch. The acknowledgement is only
+    /// returned once all values have been written.
+    pub async fn put_batch_here(&self, batch: &TransferBatch) -> io::Result<TransferAck> {
+        for item in batch.items.iter() {
+            self.put_here(&item.scope, &item.key, &item.data).await?;
+        }
+        Ok(batch.ack())
+    }
+
     pub async fn updating(&self) -> bool {
         self.updater.lock().await.is_some()
     }
 
-    pub async fn sync_updater(&'static self) {
+    pub async fn sync_updater(&'static self, myself: &NetworkId) {
         let ring = self.ring.read().await;
         let mut updater = self.updater.lock().await;
 
-        let have_update = updater.as_ref();
-        let want_update = ring.config.as_ref().and_then(|x| x.0.update.as_ref());
+        let have_update = updater.take();
+        let want_update = ring.config.as_ref().and_then(|x| x.0.update.clone());
 
-        match (have_update, want_update) {
-            (None, None) => {}
-            (None, Some(b)) => {
-                *updater = Some(b.clone());
-                tokio::spawn(self.run_update(b.clone()));
+        *updater = match (have_update, want_update) {
+            (None, None) => None,
+            (None, Some(b)) => Some(self.start_update(myself.clone(), b)),
+            (Some((a, handle)), None) => {
+                // This only happens when the controller overrides the config,
+                // e.g. for a forced bootstrap. Anything already transferred
+                // stays where it is.
+                log::warn!("update {a:?} canceled by ring config update");
+                handle.abort();
+                None
             }
-            (Some(a), None) => panic!("update {a:?} canceled by ring config update!"),
-            (Some(a), Some(b)) => {
-                if a != b {
-                    panic!("update replaced with non-equivalent update! {a:?} {b:?}");
+            (Some((a, handle)), Some(b)) => {
+                if a == b {
+                    Some((a, handle))
+                } else {
+                    log::warn!("update {a:?} replaced with non-equivalent update {b:?}");
+                    handle.abort();
+                    Some(self.start_update(myself.clone(), b))
                 }
             }
-        }
+        };
     }
 
-    async fn run_update(&self, update: RingUpdateConfig) {
-        match update {
-            RingUpdateConfig::ToAdd { vn, ni } => self.run_to_add(vn, ni).await,
-            RingUpdateConfig::ToRemove { .. } => todo!(),
-        }
-
-        let mut updater = self.updater.lock().await;
-        *updater = None;
+    fn start_update(
+        &'static self,
+        myself: NetworkId,
+        update: RingUpdateConfig,
+    ) -> (RingUpdateConfig, AbortHandle) {
+        let handle = tokio::spawn(self.run_update(myself, update.clone())).abort_handle();
+        (update, handle)
     }
 
-    async fn run_to_add(&self, vn: VirtualNodeId, ni: NetworkId) {
-        let router = CrdtRouterClient::new().at(ni.as_location());
+    /// Move every key this node owns that the update gives to another node.
+    async fn run_update(&self, myself: NetworkId, update: RingUpdateConfig) {
+        log::info!("running update {update:?}");
+        self.run_transfer(|placement, hash| {
+            if placement.owner(hash) == Some(&myself) {
+                placement.moving_to(hash).cloned()
+            } else {
+                None
+            }
+        })
+        .await;
 
-        let range = {
-            let range = self.ring.read().await.config.as_ref().unwrap().1.range(&vn);
-            range.trim_start(vn)
-        };
+        let mut updater = self.updater.lock().await;
+        if updater.as_ref().is_some_and(|(u, _)| *u == update) {
+            *updater = None;
+        }
+    }
 
+    async fn run_transfer<F>(&self, select: F)
+    where
+        F: Fn(&Placement, &KeyHash) -> Option<NetworkId>,
+    {
+        // Local copies are only deleted once the receiver has acknowledged
+        // them, so if we crash partway through, the update is restarted by
+        // sync_updater() and simply picks up whatever is left on disk.
         loop {
-            let mut num_failures = 0;
-            let mut num_transferred = 0;
-            for scope in self.list_scopes() {
-                for key in self.list_keys_in_scope(&scope) {
-                    let path = self.root.join("storage").join(mk_path(&scope, &key));
-                    let ck = CompositeKey {
-                        scope: scope.clone(),
-                        key: key.clone(),
-                    };
-                    if range.contains(&ck) {
-                        let data = tokio::fs::read(&path).await.unwrap();
-                        let res = router.put_here(scope.clone(), key.clone(), data).await;
-                        if let Ok(_) = res {
-                            num_transferred += 1;
-                            tokio::fs::remove_file(&path).await.unwrap();
-                        } else {
-                            num_failures += 1;
-                        }
-                    }
-                }
-            }
+            let (num_transferred, num_failures) = self.transfer_keys(&select).await;
             if num_failures == 0 && num_transferred == 0 {
                 break;
             } else {
@@ -173,19 +475,184 @@ impl StorageInstance {
         }
     }
 
-    fn list_scopes(&self) -> impl Iterator<Item = String> {
-        std::fs::read_dir(self.root.join("storage"))
-            .unwrap()
-            .map(|x| x.unwrap().file_name().to_str().unwrap().to_owned())
+    /// Send every stored key to the node picked for it by `select`, if any.
+    /// Returns the number of keys sent and the number that failed to send.
+    async fn transfer_keys<F>(&self, select: &F) -> (u64, u64)
+    where
+        F: Fn(&Placement, &KeyHash) -> Option<NetworkId>,
+    {
+        let mut moves: HashMap<NetworkId, Vec<CompositeKey>> = HashMap::new();
+        {
+            let ring = self.ring.read().await;
+            let Some((_, placement)) = ring.config.as_ref() else {
+                return (0, 0);
+            };
+            let keys = match self.list_keys().await {
+                Ok(x) => x,
+                Err(e) => {
+                    // Count this as a failure so that the caller tries again.
+                    log::warn!("failed to list keys: {e}");
+                    return (0, 1);
+                }
+            };
+            for ck in keys {
+                if let Some(to) = select(placement, &ck.as_sha256()) {
+                    moves.entry(to).or_default().push(ck);
+                }
+            }
+        }
+
+        let mut num_transferred = 0;
+        let mut num_failures = 0;
+        for (ni, keys) in moves {
+            log::debug!("transferring {} keys to {ni:?}", keys.len());
+            let router = CrdtRouterClient::new().at(ni.as_location());
+            let mut batch = TransferBatch::default();
+            for ck in keys {
+                let data = match self.get_here(ck.scope(), ck.key()).await {
+                    Ok(Some(x)) => x,
+                    // Already moved, e.g. by another transfer.
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::warn!("failed to read {ck:?} for transfer: {e}");
+                        num_failures += 1;
+                        continue;
+                    }
+                };
+                batch.push(ck.scope().to_owned(), ck.key().to_owned(), data);
+                if batch.is_full() {
+                    let (sent, failed) = self.send_batch(&router, std::mem::take(&mut batch)).await;
+                    num_transferred += sent;
+                    num_failures += failed;
+                }
+            }
+            if !batch.is_empty() {
+                let (sent, failed) = self.send_batch(&router, batch).await;
+                num_transferred += sent;
+                num_failures += failed;
+            }
+        }
+        (num_transferred, num_failures)
     }
 
-    fn list_keys_in_scope(&self, scope: &str) -> impl Iterator<Item = String> {
-        std::fs::read_dir(self.root.join("storage").join(scope))
-            .unwrap()
-            .map(|x| x.unwrap().file_name().to_str().unwrap().to_owned())
+    /// Send a batch and delete the local copies once it has been acknowledged.
+    /// A local copy that was written to since it was read is kept, and counted
+    /// as failed so that it is sent again. Returns the number of values
+    /// transferred and the number that failed to transfer.
+    async fn send_batch(&self, router: &CrdtRouterClient, batch: TransferBatch) -> (u64, u64) {
+        let n = batch.items.len() as u64;
+        let expected = batch.ack();
+        match router.put_batch_here(batch.clone()).await {
+            Ok(ack) if ack == expected => {}
+            Ok(ack) => {
+                log::warn!("transfer ack mismatch: expected {expected:?}, got {ack:?}");
+                return (0, n);
+            }
+            Err(e) => {
+                log::warn!("transfer batch failed: {e:?}");
+                return (0, n);
+            }
+        }
+
+        let mut num_failures = 0;
+        for item in batch.items.iter() {
+            match self
+                .remove_if_unchanged(&item.scope, &item.key, &item.data)
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => num_failures += 1,
+                Err(e) => {
+                    log::warn!(
+                        "failed to remove transferred {}/{}: {e}",
+                        item.scope,
+                        item.key
+                    );
+                    num_failures += 1;
+                }
+            }
+        }
+        (n - num_failures, num_failures)
+    }
+
+    /// Delete a stored value if it still holds `data`. Returns whether the value
+    /// is gone, which it also is if something else already deleted it.
+    async fn remove_if_unchanged(&self, scope: &str, key: &str, data: &[u8]) -> io::Result<bool> {
+        self.with_lock(scope, key, async |path| {
+            match tokio::fs::read(&path).await {
+                Ok(current) if current == data => tokio::fs::remove_file(&path).await.map(|_| true),
+                Ok(_) => Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+    }
+
+    /// Count the stored keys that the given config would not route to this
+    /// node. Such keys would become unreachable if the config were adopted.
+    pub async fn count_orphans(&self, myself: &NetworkId, cf: &RingConfig) -> io::Result<u64> {
+        let placement = Placement::new(cf);
+        let keys = self.list_keys().await?;
+        Ok((keys.into_iter())
+            .filter(|ck| !placement.holders(&ck.as_sha256()).contains(myself))
+            .count() as u64)
+    }
+
+    /// Send every stored key that the current config doesn't route to this
+    /// node to the node that it is routed to. This recovers copies left behind
+    /// by migrations that were canceled, e.g. when a node failed. Keys that are
+    /// gone by the time they're read have already been moved and are skipped.
+    /// Returns the number of keys sent, or the number of keys that couldn't be
+    /// sent.
+    pub async fn rehome(&self, myself: &NetworkId) -> Result<u64, u64> {
+        let _rehoming = self.rehoming.lock().await;
+        let (num_transferred, num_failures) = self
+            .transfer_keys(&|placement: &Placement, hash: &KeyHash| {
+                if placement.holders(hash).contains(myself) {
+                    None
+                } else {
+                    placement.owner(hash).cloned()
+                }
+            })
+            .await;
+        if num_transferred > 0 {
+            log::warn!("rehomed {num_transferred} keys");
+        }
+        if num_failures == 0 {
+            Ok(num_transferred)
+        } else {
+            Err(num_failures)
+        }
+    }
+
+    /// List every stored key. The directories are read on a blocking thread.
+    async fn list_keys(&self) -> io::Result<Vec<CompositeKey>> {
+        let storage = self.root.join("storage");
+        tokio::task::spawn_blocking(move || {
+            let mut res = Vec::new();
+            for scope in read_names(&storage)? {
+                let dir = storage.join(&scope);
+                for key in read_names(&dir)? {
+                    res.push(CompositeKey::new(
+                        mk_unsanitized(&scope),
+                        mk_unsanitized(&key),
+                    ));
+                }
+            }
+            Ok(res)
+        })
+        .await?
     }
 }
 
+/// The names of the entries of a directory.
+fn read_names(dir: &Path) -> io::Result<Vec<String>> {
+    std::fs::read_dir(dir)?
+        .map(|x| Ok(x?.file_name().to_str().unwrap().to_owned()))
+        .collect()
+}
+
 fn mk_path(scope: &str, key: &str) -> PathBuf {
     let scope = mk_sanitized(scope);
     let key = mk_sanitized(key);
@@ -202,9 +669,29 @@ fn mk_sanitized(x: &str) -> PathBuf {
     PathBuf::from(x)
 }
 
+/// Recover the original name from the output of [`mk_sanitized`].
+fn mk_unsanitized(x: &str) -> String {
+    x.replace("%00", "\0")
+        .replace("%3F", "?")
+        .replace("%2F", "/")
+        .replace("%2A", "*")
+        .replace("%25", "%")
+}
+
+async fn load_controls(path: &PathBuf) -> ControllerControls {
+    if path.exists() {
+        let data = tokio::fs::read(path)
+            .await
+            .expect("could not read controls file");
+        serde_json::from_slice(&data).expect("could not parse controls file")
+    } else {
+        ControllerControls::default()
+    }
+}
+
 struct RingStorage {
     path: PathBuf,
-    config: Option<(RingConfig, HashRing)>,
+    config: Option<(RingConfig, Placement)>,
 }
 
 impl RingStorage {
@@ -214,8 +701,8 @@ impl RingStorage {
                 .await
                 .expect("could not read ring config file");
             let config = serde_json::from_slice(&data).expect("could not parse ring config file");
-            let ring = HashRing::from_config(&config);
-            Some((config, ring))
+            let placement = Placement::new(&config);
+            Some((config, placement))
         } else {
             None
         };
@@ -228,8 +715,8 @@ impl RingStorage {
         tokio::fs::write(&self.path, data)
             .await
             .expect("write ring config failed");
-        let ring = HashRing::from_config(&config);
-        self.config = Some((config, ring));
+        let placement = Placement::new(&config);
+        self.config = Some((config, placement));
     }
 }
 
@@ -0,0 +1,238 @@
+//! Checks that [`Crdt`] implementations obey the laws the trait requires.
+//!
+//! [`check_laws`] generates values and checks that merging them is
+//! idempotent, commutative, and associative, and that
+//! [`merge`][Crdt::merge] agrees with [`merge_from`][Crdt::merge_from]. A
+//! violation panics with the values involved and the seed that reproduces it,
+//! so it can be called from a test. Generators should pick from small ranges
+//! so that the values they make overlap.
+//!
+//! The generator is seeded with a fixed seed, so that a test either always
+//! passes or always fails. Setting the `HAZE_LAW_SEED` environment variable
+//! checks other values, and [`LawChecker::with_random_seed`] picks a new seed
+//! every time. Either way the seed is printed, so that a failure can be
+//! reproduced.
+//!
+//! ```
+//! use amimono_haze::crdt::{crdt::Max, testing::check_laws};
+//! use rand::Rng;
+//!
+//! check_laws(|rng| Max(rng.random_range(0..10u8)));
+//! ```
+
+use std::fmt;
+
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::crdt::Crdt;
+
+/// A law that a pair or triple of values doesn't obey.
+#[derive(Clone, Debug)]
+pub struct LawViolation {
+    pub law: &'static str,
+    pub detail: String,
+}
+
+impl fmt::Display for LawViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} violated: {}", self.law, self.detail)
+    }
+}
+
+impl std::error::Error for LawViolation {}
+
+/// The seed used unless another one is asked for.
+const DEFAULT_SEED: u64 = 0x5eed;
+
+/// The environment variable that overrides the default seed.
+const SEED_VAR: &str = "HAZE_LAW_SEED";
+
+#[derive(Clone, Debug)]
+enum Seed {
+    Default,
+    Fixed(u64),
+    Random,
+}
+
+/// Runs the law checks on generated values.
+#[derive(Clone, Debug)]
+pub struct LawChecker {
+    cases: usize,
+    seed: Seed,
+}
+
+impl Default for LawChecker {
+    fn default() -> Self {
+        LawChecker {
+            cases: 256,
+            seed: Seed::Default,
+        }
+    }
+}
+
+impl LawChecker {
+    pub fn new() -> LawChecker {
+        LawChecker::default()
+    }
+
+    /// Check this many triples of values. The default is 256.
+    pub fn with_cases(mut self, cases: usize) -> LawChecker {
+        self.cases = cases;
+        self
+    }
+
+    /// Seed the generator, e.g. to reproduce a failure. This takes precedence
+    /// over `HAZE_LAW_SEED`.
+    pub fn with_seed(mut self, seed: u64) -> LawChecker {
+        self.seed = Seed::Fixed(seed);
+        self
+    }
+
+    /// Seed the generator with a new random seed every time, unless
+    /// `HAZE_LAW_SEED` is set.
+    pub fn with_random_seed(mut self) -> LawChecker {
+        self.seed = Seed::Random;
+        self
+    }
+
+    fn seed(&self) -> u64 {
+        if let Seed::Fixed(seed) = self.seed {
+            return seed;
+        }
+        let seed = match std::env::var(SEED_VAR) {
+            Ok(x) => x
+                .parse()
+                .unwrap_or_else(|e| panic!("{SEED_VAR}={x:?} is not a u64: {e}")),
+            Err(_) => match self.seed {
+                Seed::Random => rand::random(),
+                _ => return DEFAULT_SEED,
+            },
+        };
+        eprintln!("checking laws with seed {seed}");
+        seed
+    }
+
+    /// Check every law on triples of generated values, and panic on the first
+    /// violation.
+    pub fn check<T, F>(&self, mut generate: F)
+    where
+        T: Crdt + Clone + PartialEq + fmt::Debug,
+        F: FnMut(&mut StdRng) -> T,
+    {
+        let seed = self.seed();
+        let mut rng = StdRng::seed_from_u64(seed);
+        for case in 0..self.cases {
+            let a = generate(&mut rng);
+            let b = generate(&mut rng);
+            let c = generate(&mut rng);
+            let res = check_idempotent(&a)
+                .and_then(|_| check_commutative(&a, &b))
+                .and_then(|_| check_associative(&a, &b, &c))
+                .and_then(|_| check_merge_agrees(&a, &b));
+            if let Err(e) = res {
+                panic!("{e} (seed {seed}, case {case})");
+            }
+        }
+    }
+}
+
+/// Check every law on triples of generated values with the default
+/// [`LawChecker`], and panic on the first violation.
+pub fn check_laws<T, F>(generate: F)
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+    F: FnMut(&mut StdRng) -> T,
+{
+    LawChecker::new().check(generate)
+}
+
+/// Check the laws on copies of `base`, each changed by `change` as a replica of
+/// its own, so that the copies diverge the way replicas do.
+#[cfg(test)]
+pub(crate) fn check_replica_laws<T, F>(base: &T, mut change: F)
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+    F: FnMut(&mut StdRng, &mut T, &crate::crdt::crdt::ReplicaId),
+{
+    let mut n = 0;
+    check_laws(|rng| {
+        n += 1;
+        let mut x = base.clone();
+        let replica = crate::crdt::crdt::ReplicaId::new(format!("r{n}"));
+        change(rng, &mut x, &replica);
+        x
+    });
+}
+
+/// Check that merging a value with itself gives the same value.
+pub fn check_idempotent<T>(a: &T) -> Result<(), LawViolation>
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+{
+    let aa = a.clone().merge(a.clone());
+    if aa != *a {
+        return Err(LawViolation {
+            law: "idempotence",
+            detail: format!("a = {a:?}, but a.merge(a) = {aa:?}"),
+        });
+    }
+    Ok(())
+}
+
+/// Check that the order of two merged values doesn't matter.
+pub fn check_commutative<T>(a: &T, b: &T) -> Result<(), LawViolation>
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+{
+    let ab = a.clone().merge(b.clone());
+    let ba = b.clone().merge(a.clone());
+    if ab != ba {
+        return Err(LawViolation {
+            law: "commutativity",
+            detail: format!(
+                "a = {a:?}, b = {b:?}, but a.merge(b) = {ab:?} and b.merge(a) = {ba:?}"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Check that the grouping of three merged values doesn't matter.
+pub fn check_associative<T>(a: &T, b: &T, c: &T) -> Result<(), LawViolation>
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+{
+    let ab_c = a.clone().merge(b.clone()).merge(c.clone());
+    let a_bc = a.clone().merge(b.clone().merge(c.clone()));
+    if ab_c != a_bc {
+        return Err(LawViolation {
+            law: "associativity",
+            detail: format!(
+                "a = {a:?}, b = {b:?}, c = {c:?}, but (a.merge(b)).merge(c) = {ab_c:?} \
+                 and a.merge(b.merge(c)) = {a_bc:?}"
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Check that [`merge`][Crdt::merge] and [`merge_from`][Crdt::merge_from]
+/// give the same value.
+pub fn check_merge_agrees<T>(a: &T, b: &T) -> Result<(), LawViolation>
+where
+    T: Crdt + Clone + PartialEq + fmt::Debug,
+{
+    let merged = a.clone().merge(b.clone());
+    let mut merged_from = a.clone();
+    merged_from.merge_from(b.clone());
+    if merged != merged_from {
+        return Err(LawViolation {
+            law: "agreement of merge and merge_from",
+            detail: format!(
+                "a = {a:?}, b = {b:?}, but a.merge(b) = {merged:?} and a.merge_from(b) \
+                 gives {merged_from:?}"
+            ),
+        });
+    }
+    Ok(())
+}
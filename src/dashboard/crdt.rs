@@ -1,23 +1,237 @@
+use std::{collections::BTreeMap, f64::consts::PI, fmt::Write};
+
 use crate::{
-    crdt::router::CrdtRouterClient,
-    dashboard::tree::{BoxDirectory, DirEntry, Directory, Item, TreeError, TreeResult},
+    crdt::{
+        CrdtAdmin, admin,
+        balance::RingBalance,
+        partition::{self, KeyHash, KeyRange},
+        ring::{NetworkId, RingConfig, RingUpdateConfig},
+        router::CrdtRouterClient,
+    },
+    dashboard::tree::{BoxDirectory, DirEntry, Directory, Item, TreeError, TreeResult, escape},
 };
 
 pub struct CrdtDirectory;
 
 impl Directory for CrdtDirectory {
     async fn list(&self) -> TreeResult<Vec<DirEntry>> {
-        Ok(vec![DirEntry::item("config")])
+        Ok(vec![
+            DirEntry::item("balance"),
+            DirEntry::item("config"),
+            DirEntry::item("controls"),
+            DirEntry::dir("explain"),
+            DirEntry::item("plan"),
+        ])
     }
 
-    async fn open_dir(&self, _name: &str) -> TreeResult<BoxDirectory> {
-        Err(TreeError::NotFound)
+    async fn open_dir(&self, name: &str) -> TreeResult<BoxDirectory> {
+        match name {
+            "explain" => Ok(ExplainDirectory.boxed()),
+            _ => Err(TreeError::NotFound),
+        }
     }
 
     async fn open_item(&self, name: &str) -> TreeResult<Item> {
         match name {
+            "balance" => match CrdtRouterClient::new().get_ring().await? {
+                Some(cf) => Ok(Item::html(render_balance(&cf))),
+                None => Ok(Item::new("no ring config")),
+            },
             "config" => Ok(Item::json(&CrdtRouterClient::new().get_ring().await?)),
+            "controls" => {
+                let (controls, _) = admin::read_controls(&CrdtRouterClient::new()).await?;
+                Ok(Item::json(&controls))
+            }
+            "plan" => Ok(Item::json(&CrdtRouterClient::new().get_plan().await?)),
             _ => Err(TreeError::NotFound),
         }
     }
 }
+
+/// Explains the placement of any key, at `explain/<scope>/<key>.html`. Scopes
+/// and keys are not listed.
+struct ExplainDirectory;
+
+impl Directory for ExplainDirectory {
+    async fn list(&self) -> TreeResult<Vec<DirEntry>> {
+        Ok(Vec::new())
+    }
+
+    async fn open_dir(&self, name: &str) -> TreeResult<BoxDirectory> {
+        Ok(ExplainScopeDirectory(name.to_owned()).boxed())
+    }
+
+    async fn open_item(&self, _name: &str) -> TreeResult<Item> {
+        Err(TreeError::NotFound)
+    }
+}
+
+struct ExplainScopeDirectory(String);
+
+impl Directory for ExplainScopeDirectory {
+    async fn list(&self) -> TreeResult<Vec<DirEntry>> {
+        Ok(Vec::new())
+    }
+
+    async fn open_dir(&self, _name: &str) -> TreeResult<BoxDirectory> {
+        Err(TreeError::NotFound)
+    }
+
+    async fn open_item(&self, name: &str) -> TreeResult<Item> {
+        let placement = CrdtAdmin::new().explain(&self.0, name).await?;
+        Ok(Item::json(&placement))
+    }
+}
+
+const SVG_SIZE: f64 = 400.0;
+const SVG_INNER: f64 = 110.0;
+const SVG_OUTER: f64 = 160.0;
+const SVG_UPDATE: f64 = 185.0;
+
+/// Render the balance of a ring config as a table and a diagram of the ring.
+fn render_balance(cf: &RingConfig) -> String {
+    let balance = RingBalance::of_config(cf);
+
+    let mut nodes: Vec<&NetworkId> = balance.nodes.iter().map(|x| &x.node).collect();
+    if let Some(RingUpdateConfig::ToAdd { ni, .. }) = cf.update.as_ref()
+        && !nodes.contains(&ni)
+    {
+        nodes.push(ni);
+    }
+    let colors: BTreeMap<&NetworkId, String> = (nodes.iter().enumerate())
+        .map(|(i, ni)| {
+            (
+                *ni,
+                format!("hsl({:.0}, 60%, 55%)", (i as f64 * 137.5) % 360.0),
+            )
+        })
+        .collect();
+
+    let mut out = String::new();
+    out.push_str(
+        "<table><tr><th></th><th>node</th><th>vnodes</th><th>share</th><th>vs. mean</th></tr>",
+    );
+    for share in balance.nodes.iter() {
+        write!(
+            out,
+            r#"<tr><td style="background: {}"></td><td>{}</td><td>{}</td><td>{:.2}%</td><td>{:.2}</td></tr>"#,
+            colors[&share.node],
+            escape(&share.node.0),
+            share.vnodes,
+            share.fraction * 100.0,
+            share.fraction / balance.mean,
+        )
+        .unwrap();
+    }
+    write!(
+        out,
+        "</table><p>min {:.2}%, max {:.2}%, mean {:.2}%, stddev {:.2}%, imbalance {:.2}</p>",
+        balance.min * 100.0,
+        balance.max * 100.0,
+        balance.mean * 100.0,
+        balance.stddev * 100.0,
+        balance.imbalance,
+    )
+    .unwrap();
+
+    match cf.update.as_ref() {
+        Some(RingUpdateConfig::ToAdd { vn, ni }) => {
+            let (vn, ni) = (escape(&vn.0), escape(&ni.0));
+            write!(
+                out,
+                "<p>Adding {vn} to {ni}. The outer band shows where keys are moving to.</p>"
+            )
+            .unwrap();
+        }
+        Some(RingUpdateConfig::ToRemove { vn, ni }) => {
+            let (vn, ni) = (escape(&vn.0), escape(&ni.0));
+            write!(
+                out,
+                "<p>Removing {vn} from {ni}. The outer band shows where keys are moving to.</p>"
+            )
+            .unwrap();
+        }
+        None => {}
+    }
+
+    let half = SVG_SIZE / 2.0;
+    write!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{SVG_SIZE}" height="{SVG_SIZE}" viewBox="{} {} {SVG_SIZE} {SVG_SIZE}">"#,
+        -half, -half
+    )
+    .unwrap();
+
+    let current = cf.partitioner.build(cf.nodes.keys().cloned());
+    let mut owned: Vec<(KeyRange, &NetworkId)> = Vec::new();
+    let ranges = current.ranges();
+    for (i, (start, vn)) in ranges.iter().enumerate() {
+        let end = ranges[(i + 1) % ranges.len()].0;
+        let Some(ni) = cf.network_id(vn) else {
+            continue;
+        };
+        // Merge neighboring ranges of the same node.
+        if let Some((last, last_ni)) = owned.last_mut()
+            && last.end == *start
+            && *last_ni == ni
+        {
+            last.end = end;
+            continue;
+        }
+        owned.push((KeyRange { start: *start, end }, ni));
+    }
+    for (range, ni) in owned.iter() {
+        let title = escape(&format!("{}: {:.2}%", ni.0, range.fraction() * 100.0));
+        write_arc(&mut out, range, SVG_INNER, SVG_OUTER, &colors[ni], &title);
+    }
+
+    if cf.update.is_some() {
+        let updated = cf.updated_nodes();
+        let next = cf.partitioner.build(updated.keys().cloned());
+        for m in partition::moves(current.as_ref(), next.as_ref()) {
+            let Some(ni) = m.to.as_ref().and_then(|vn| updated.get(vn)) else {
+                continue;
+            };
+            let title = escape(&format!("to {}: {:.2}%", ni.0, m.range.fraction() * 100.0));
+            write_arc(
+                &mut out,
+                &m.range,
+                SVG_OUTER + 5.0,
+                SVG_UPDATE,
+                &colors[ni],
+                &title,
+            );
+        }
+    }
+
+    out.push_str("</svg>");
+    out
+}
+
+/// Write an SVG path for the part of an annulus covering a range of keys.
+fn write_arc(out: &mut String, range: &KeyRange, r0: f64, r1: f64, color: &str, title: &str) {
+    let point = |r: f64, a: f64| (r * a.sin(), -r * a.cos());
+
+    let a0 = angle(&range.start);
+    let span = range.fraction() * 2.0 * PI;
+    // A path can't describe a full circle with a single arc, so stop just
+    // short of it.
+    let a1 = a0 + span.min(2.0 * PI - 1e-6);
+    let large = if span > PI { 1 } else { 0 };
+
+    let (x0, y0) = point(r1, a0);
+    let (x1, y1) = point(r1, a1);
+    let (x2, y2) = point(r0, a1);
+    let (x3, y3) = point(r0, a0);
+    write!(
+        out,
+        r#"<path d="M {x0:.2} {y0:.2} A {r1} {r1} 0 {large} 1 {x1:.2} {y1:.2} L {x2:.2} {y2:.2} A {r0} {r0} 0 {large} 0 {x3:.2} {y3:.2} Z" fill="{color}" stroke="white" stroke-width="0.5"><title>{title}</title></path>"#,
+    )
+    .unwrap();
+}
+
+/// The angle of a key hash on the ring, clockwise from the top.
+fn angle(hash: &KeyHash) -> f64 {
+    let prefix = u64::from_be_bytes(hash[..8].try_into().unwrap());
+    prefix as f64 / 2f64.powi(64) * 2.0 * PI
+}
@@ -99,12 +99,23 @@ impl DirEntry {
 
 pub struct Item {
     pub value: String,
+    pub is_html: bool,
 }
 
 impl Item {
     pub fn new<S: Into<String>>(value: S) -> Item {
         Item {
             value: value.into(),
+            is_html: false,
+        }
+    }
+
+    /// An item that is rendered as-is, rather than as preformatted text. Any
+    /// text in it must be passed through [`escape`].
+    pub fn html<S: Into<String>>(value: S) -> Item {
+        Item {
+            value: value.into(),
+            is_html: true,
         }
     }
 
@@ -119,6 +130,14 @@ impl From<String> for Item {
     }
 }
 
+/// Escape text for inclusion in HTML.
+pub fn escape(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+}
+
 fn encode_name(s: &'_ str) -> Cow<'_, str> {
     if s.is_empty() {
         Cow::Borrowed("%00")
@@ -225,19 +244,16 @@ async fn render_list(cur: BoxDirectory, title: &str) -> TreeResponse {
 
 async fn render_item(cur: BoxDirectory, title: &str, name: &str) -> TreeResponse {
     let (status, contents) = match cur.0.open_item(name).await {
+        Ok(item) if item.is_html => (StatusCode::OK, format!(r#"<div>{}</div>"#, item.value)),
         Ok(item) => {
-            let clean = item
-                .value
-                .replace("&", "&amp;")
-                .replace("<", "&lt;")
-                .replace(">", "&gt;");
-            (StatusCode::OK, clean)
+            let clean = escape(&item.value);
+            (StatusCode::OK, format!(r#"<p class="item">{clean}</p>"#))
         }
         Err(TreeError::NotFound) => {
             return render_404();
         }
         Err(TreeError::Other(s)) => {
-            let contents = format!("<em>Error: {s}</em>");
+            let contents = format!(r#"<p class="item"><em>Error: {s}</em></p>"#);
             (StatusCode::INTERNAL_SERVER_ERROR, contents)
         }
     };
@@ -255,7 +271,7 @@ async fn render_item(cur: BoxDirectory, title: &str, name: &str) -> TreeResponse
             <ul class="dir">
             <li><a href="./">Back</a></li>
             </ul>
-            <p class="item">{contents}</p>
+            {contents}
         </body>
         </html>"#
     );
@@ -283,4 +299,7 @@ const CSS: &'static str = r#"
     a:hover { text-decoration: underline; }
     p.item { font-family: monospace; white-space: pre-wrap;
         padding: 2em; background-color: #f3f3f3; }
+    table { border-collapse: collapse; margin: 1em 0; }
+    th, td { padding: 0.3em 1em; text-align: right; font-family: monospace; }
+    th { border-bottom: 1px solid #999; }
 </style>"#;
@@ -7,6 +7,14 @@ pub mod dashboard;
 
 pub(crate) mod util;
 
+/// Dependencies named by code generated in other crates, such as by the
+/// derive macros. Not part of the public API.
+#[cfg(feature = "crdt")]
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
+
 pub fn installer() -> impl FnOnce(&mut AppBuilder) {
     installer_with_prefix("haze")
 }